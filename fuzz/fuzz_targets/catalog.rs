@@ -0,0 +1,38 @@
+#![no_main]
+
+// The catalog loader and version selection over whatever catalog it accepts
+use libfuzzer_sys::fuzz_target;
+use updateserver::{default_request, default_version, latest, Channel};
+use updateserver::version::{parse_number, parse_versions};
+
+const CHANNELS:[Channel; 5] = [Channel::Stable, Channel::Beta, Channel::Dev, Channel::Canary, Channel::Extended];
+
+fuzz_target!(|data:&[u8]| {
+    let Ok(contents) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(versions) = parse_versions(contents) else {
+        return;
+    };
+    let _ = versions.to_string();
+    let default_version = default_version();
+    for channel in CHANNELS {
+        let mut request = default_request();
+        request.channel = channel.clone();
+        request.os.platform = String::from("Linux");
+        request.os.arch = String::from("x86_64");
+        request.installid = String::from("fuzz");
+        for version in versions.channel(&channel) {
+            let _ = parse_number(&version.number());
+            let _ = version.mirror_set();
+            let _ = latest::rollback_target(versions.channel(&channel), version);
+            let _ = latest::last_good_version(versions.channel(&channel), version);
+        }
+        let _ = latest::select_version(&versions, &request, &default_version);
+        // a client on every listed version, halted ones get their rollback
+        for version in versions.channel(&channel) {
+            request.version = version.number();
+            let _ = latest::select_version(&versions, &request, &default_version);
+        }
+    }
+});
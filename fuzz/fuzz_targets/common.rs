@@ -0,0 +1,37 @@
+// A server with a small catalog on every channel, shared by the JSON targets
+use std::fs;
+use updateserver::{catalog, Server};
+use updateserver::http::{HttpRequest, Response};
+
+const CATALOG:&str = r#"{
+    "stable":[{"major":1,"minor":0,"build":0,"patch":0,"count":0,"urls":["https://example.com/stable.tar.gz"]}],
+    "beta":[{"major":1,"minor":1,"build":0,"patch":0,"count":0,"urls":[],"rollout":50}],
+    "dev":[
+        {"major":1,"minor":2,"build":0,"patch":0,"count":0,"urls":["https://example.com/dev.tar.gz"],"halted":true,"rollback":"1.1.5"},
+        {"major":1,"minor":1,"build":5,"patch":0,"count":0,"urls":["https://example.com/dev-old.tar.gz"]}
+    ],
+    "canary":[{"major":1,"minor":3,"build":0,"patch":0,"count":0,"urls":["https://example.com/canary.tar.gz"],"hardware":{"sse":1,"physmemory":4}}],
+    "extended":[]
+}"#;
+
+thread_local! {
+    static SERVER:Server = start();
+}
+
+fn start() -> Server {
+    let dir = std::env::temp_dir().join(format!("updateserver-fuzz-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let versions_path = dir.join("versions.json");
+    fs::write(&versions_path, CATALOG).unwrap();
+    let products = catalog::load_products(
+        &dir.join("products.json").to_string_lossy(),
+        &versions_path.to_string_lossy(),
+        &dir.join("catalogs").to_string_lossy()
+    ).unwrap();
+    Server::new(products, Default::default())
+}
+
+pub fn post(path:&str, body:&str) -> Response {
+    let request = HttpRequest::new(&format!("POST {path} HTTP/1.1"), String::new(), String::from(body)).unwrap();
+    SERVER.with(|server| server.handle(&request))
+}
@@ -0,0 +1,23 @@
+#![no_main]
+
+// The request line and header parser: everything a client sends before the router sees it
+use libfuzzer_sys::fuzz_target;
+use updateserver::{default_request, query};
+use updateserver::http::{self, HttpRequest, RequestBody};
+
+fuzz_target!(|data:&[u8]| {
+    let mut reader = data;
+    while let Ok(Some(raw)) = http::read_request(&mut reader) {
+        let RequestBody::Complete(body) = raw.body else {
+            continue;
+        };
+        let Some(request) = HttpRequest::new(&raw.request_line, raw.headers, body) else {
+            continue;
+        };
+        let _ = request.header("Connection");
+        let _ = request.header("Accept-Encoding");
+        let _ = query::parse_query(&request.query);
+        let mut value = serde_json::to_value(default_request()).unwrap();
+        let _ = query::apply_query(&mut value, &request.query, None);
+    }
+});
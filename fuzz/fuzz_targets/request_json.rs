@@ -0,0 +1,21 @@
+#![no_main]
+
+// The Request deserializer and the update check and download behind it
+use libfuzzer_sys::fuzz_target;
+use updateserver::Request;
+
+#[path = "common.rs"]
+mod common;
+
+fuzz_target!(|data:&[u8]| {
+    let Ok(body) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(request) = serde_json::from_str::<Request>(body) {
+        // whatever was accepted has to survive a round trip, sessions are stored that way
+        let encoded = serde_json::to_string(&request).unwrap();
+        serde_json::from_str::<Request>(&encoded).unwrap();
+    }
+    common::post("/latest", body);
+    common::post("/download", body);
+});
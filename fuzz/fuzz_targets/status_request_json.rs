@@ -0,0 +1,21 @@
+#![no_main]
+
+// The StatusRequest deserializer and the session handling behind /status
+use libfuzzer_sys::fuzz_target;
+use updateserver::StatusRequest;
+
+#[path = "common.rs"]
+mod common;
+
+fuzz_target!(|data:&[u8]| {
+    let Ok(body) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(status_request) = serde_json::from_str::<StatusRequest>(body) {
+        let encoded = serde_json::to_string(&status_request).unwrap();
+        serde_json::from_str::<StatusRequest>(&encoded).unwrap();
+        // start a session with the same request first, so the status can find one
+        common::post("/latest", &serde_json::to_string(&status_request.request).unwrap());
+    }
+    common::post("/status", body);
+});
@@ -0,0 +1,246 @@
+use std::env;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use crate::{latest, Channel};
+use crate::catalog::{AppCatalog, Products};
+use crate::version::{lock_versions, read_versions, save_versions, Versions};
+use crate::http::{create_response, header_value, HttpRequest, Response};
+
+// Admin endpoints change the catalog of the running server, every change is made to the
+// catalog file of the app and the server answers from the changed file. Requests have to carry
+// the token from UPDATESERVER_ADMIN_TOKEN in the X-Admin-Token header, the endpoints are
+// disabled when the variable isn't set.
+const ADMIN_TOKEN_VAR:&str = "UPDATESERVER_ADMIN_TOKEN";
+
+#[derive(Serialize, Deserialize)]
+struct RolloutRequest{
+    #[serde(default)]
+    product:String, // empty for the default product
+    #[serde(default)]
+    appid:String, // component to change, empty for the product itself
+    channel:Channel,
+    version:String, // "major.minor.build" or "major.minor.build.patch"
+    percentage:u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HaltRequest{
+    #[serde(default)]
+    product:String, // empty for the default product
+    #[serde(default)]
+    appid:String,
+    channel:Channel,
+    version:String,
+    #[serde(default = "halt_default")]
+    halted:bool, // false lifts the halt and clears the rollback target
+}
+
+fn halt_default() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize)]
+struct RollbackRequest{
+    #[serde(default)]
+    product:String, // empty for the default product
+    #[serde(default)]
+    appid:String,
+    channel:Channel,
+    version:String, // the bad version, it gets halted as well
+    #[serde(default)]
+    target:String, // defaults to the last good version released before the bad one
+}
+
+#[derive(Serialize, Deserialize)]
+struct AdminResponse{
+    ok:bool,
+    info:String,
+}
+
+fn admin_response(status_code:i32, ok:bool, info:String) -> Response {
+    let response_object = AdminResponse{ ok, info };
+    create_response(status_code, &serde_json::to_string(&response_object).unwrap())
+}
+
+// The admin token of the server, None disables the admin endpoints
+pub fn token() -> Option<String> {
+    env::var(ADMIN_TOKEN_VAR).ok().filter(|token| !token.is_empty())
+}
+
+// Digests are compared in constant time, so neither the time taken nor the token's length
+// tells a client how much of its guess was right
+fn is_authorized(headers:&str, admin_token:Option<&str>) -> bool {
+    let Some(expected) = admin_token else {
+        return false;
+    };
+    header_value(headers, "X-Admin-Token")
+        .is_some_and(|token| bool::from(Sha256::digest(token.as_bytes()).ct_eq(&Sha256::digest(expected.as_bytes()))))
+}
+
+// The router only lets POST through, what's left to check is the token
+fn check_request(request:&HttpRequest, admin_token:Option<&str>) -> Result<(), Response> {
+    if !is_authorized(&request.headers, admin_token) {
+        return Err(admin_response(403, false, String::from("missing or invalid admin token")));
+    }
+    Ok(())
+}
+
+fn parse_body<T: DeserializeOwned>(body:&str) -> Result<T, Response> {
+    serde_json::from_str::<T>(body).map_err(|err| admin_response(400, false, format!("invalid admin request: {err}")))
+}
+
+fn not_found(version:&str, channel:&Channel) -> Response {
+    admin_response(404, false, format!("version {} not found on channel {}", version, channel))
+}
+
+fn find_app<'a>(products:&'a mut Products, product:&str, appid:&str) -> Result<&'a mut AppCatalog, Response> {
+    let Some(catalog) = products.get_mut(product) else {
+        return Err(admin_response(404, false, format!("unknown product {product}")));
+    };
+    catalog.app_mut(appid).ok_or_else(|| admin_response(404, false, format!("unknown application {appid}")))
+}
+
+// The change is made to the file as it is on disk, under its lock, so what `updateserver delta`
+// saved meanwhile is kept. change gets the channels in the file's order and returns the info of
+// the answer, or the answer when it refuses. The server keeps the changed catalog even when
+// saving it fails.
+fn change_app(products:&mut Products, product:&str, appid:&str, change:impl FnOnce(&mut Versions) -> Result<String, Response>) -> Response {
+    let app = match find_app(products, product, appid) {
+        Ok(app) => app,
+        Err(response) => return response
+    };
+    let _lock = match lock_versions(&app.path) {
+        Ok(lock) => lock,
+        Err(err) => return admin_response(500, false, format!("failed to lock {}: {}", app.path, err))
+    };
+    let mut versions = match read_versions(&app.path) {
+        Ok(versions) => versions,
+        Err(err) => return admin_response(500, false, err)
+    };
+
+    let info = match change(&mut versions) {
+        Ok(info) => info,
+        Err(response) => return response
+    };
+    println!("{}", info);
+    let saved = save_versions(&app.path, &versions);
+    versions.sort();
+    app.versions = versions;
+    match saved {
+        Ok(_) => admin_response(200, true, info),
+        Err(err) => admin_response(500, false, format!("{info}, but saving the catalog failed: {err}"))
+    }
+}
+
+// POST /admin/rollout {"channel":"Stable","version":"0.3.1","percentage":25}
+pub fn handle_rollout(request:&HttpRequest, products:&mut Products, admin_token:Option<&str>) -> Response {
+    if let Err(response) = check_request(request, admin_token) {
+        return response;
+    }
+
+    let rollout_request = match parse_body::<RolloutRequest>(&request.body) {
+        Ok(rollout_request) => rollout_request,
+        Err(response) => return response
+    };
+
+    if rollout_request.percentage > 100 {
+        return admin_response(400, false, String::from("percentage must be between 0 and 100"));
+    }
+
+    change_app(products, &rollout_request.product, &rollout_request.appid, |versions| {
+        let Some(version) = versions.find_mut(&rollout_request.channel, &rollout_request.version) else {
+            return Err(not_found(&rollout_request.version, &rollout_request.channel));
+        };
+        let previous = version.rollout;
+        version.rollout = rollout_request.percentage;
+        Ok(format!("rollout of {} on {} changed from {}% to {}%", version.number(), rollout_request.channel, previous, version.rollout))
+    })
+}
+
+// POST /admin/halt {"channel":"Stable","version":"0.3.1"}
+pub fn handle_halt(request:&HttpRequest, products:&mut Products, admin_token:Option<&str>) -> Response {
+    if let Err(response) = check_request(request, admin_token) {
+        return response;
+    }
+
+    let halt_request = match parse_body::<HaltRequest>(&request.body) {
+        Ok(halt_request) => halt_request,
+        Err(response) => return response
+    };
+
+    change_app(products, &halt_request.product, &halt_request.appid, |versions| {
+        let Some(version) = versions.find_mut(&halt_request.channel, &halt_request.version) else {
+            return Err(not_found(&halt_request.version, &halt_request.channel));
+        };
+        version.halted = halt_request.halted;
+        if !version.halted {
+            version.rollback.clear();
+        }
+        let state = if version.halted { "halted" } else { "resumed" };
+        Ok(format!("{} on {} {}", version.number(), halt_request.channel, state))
+    })
+}
+
+// POST /admin/rollback {"channel":"Stable","version":"0.3.1","target":"0.2.1"}
+pub fn handle_rollback(request:&HttpRequest, products:&mut Products, admin_token:Option<&str>) -> Response {
+    if let Err(response) = check_request(request, admin_token) {
+        return response;
+    }
+
+    let rollback_request = match parse_body::<RollbackRequest>(&request.body) {
+        Ok(rollback_request) => rollback_request,
+        Err(response) => return response
+    };
+
+    change_app(products, &rollback_request.product, &rollback_request.appid, |versions| {
+        let channel = versions.channel(&rollback_request.channel);
+        let Some(bad_version) = channel.iter().find(|version| version.matches(&rollback_request.version)) else {
+            return Err(not_found(&rollback_request.version, &rollback_request.channel));
+        };
+
+        let target = if rollback_request.target.is_empty() {
+            latest::last_good_version(channel, bad_version)
+        } else {
+            channel.iter().find(|version| version.matches(&rollback_request.target))
+        };
+        let target = match target {
+            Some(target) if target.halted => {
+                return Err(admin_response(409, false, format!("rollback target {} is halted", target.number())));
+            },
+            Some(target) if target.key() == bad_version.key() => {
+                return Err(admin_response(409, false, String::from("a version can't be rolled back to itself")));
+            },
+            Some(target) => target.number(),
+            None => {
+                return Err(admin_response(404, false, format!("no rollback target found for {}", bad_version.number())));
+            }
+        };
+
+        let bad_version = versions.find_mut(&rollback_request.channel, &rollback_request.version).unwrap();
+        bad_version.halted = true;
+        bad_version.rollback = target;
+        Ok(format!("{} on {} halted, clients are rolled back to {}", bad_version.number(), rollback_request.channel, bad_version.rollback))
+    })
+}
+
+// POST /admin/reload, e.g. after `updateserver delta` added patches. The server keeps its
+// catalogs when one of them fails to load.
+pub fn handle_reload(request:&HttpRequest, products:&mut Products, admin_token:Option<&str>) -> Response {
+    if let Err(response) = check_request(request, admin_token) {
+        return response;
+    }
+
+    match products.reload() {
+        Ok(_) => {
+            let info = format!("{} products reloaded", products.catalogs.len());
+            println!("{}", info);
+            admin_response(200, true, info)
+        },
+        Err(err) => {
+            println!("Keeping the current catalogs, reload failed: {}", err);
+            admin_response(500, false, format!("reload failed, the current catalogs are kept: {err}"))
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use crate::query;
+use crate::compression::COMPRESSION_MAX_SIZE;
+use crate::http::{create_response_with_type, Body, HttpRequest, Response};
+
+// Static artifact store: package files under the artifacts directory are served from
+// /artifacts/<path>, so catalog urls can point at this server. Downloads are resumable with
+// Range requests, and If-None-Match lets clients skip files they already have.
+pub const ARTIFACTS_ROUTE:&str = "/artifacts/";
+const ARTIFACTS_DIR_VAR:&str = "UPDATESERVER_ARTIFACTS_DIR";
+pub const ARTIFACTS_DIR:&str = "artifacts";
+
+pub fn artifacts_dir() -> PathBuf {
+    match env::var(ARTIFACTS_DIR_VAR) {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(ARTIFACTS_DIR)
+    }
+}
+
+// Only plain relative paths are served, anything that could leave the store is rejected
+pub fn resolve(root:&Path, relative:&str) -> Option<PathBuf> {
+    let relative = Path::new(relative);
+    if relative.as_os_str().is_empty() || !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        return None;
+    }
+    Some(root.join(relative))
+}
+
+// Size and modification time change whenever a package is replaced, hashing hundreds of MB
+// on every request isn't worth it
+fn etag(metadata:&fs::Metadata) -> String {
+    let modified = metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_nanos())
+        .unwrap_or(0);
+    format!("\"{:x}-{:x}\"", metadata.len(), modified)
+}
+
+// Release notes and listings get their own type so they can be compressed, packages and
+// patches stay application/octet-stream and are sent as they are
+fn content_type(path:&Path) -> &'static str {
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+    match extension.as_str() {
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "html" | "htm" => "text/html",
+        "txt" | "md" => "text/plain",
+        "gz" | "tgz" => "application/gzip",
+        "zst" => "application/zstd",
+        "xz" => "application/x-xz",
+        "zip" => "application/zip",
+        _ => "application/octet-stream"
+    }
+}
+
+fn etag_matches(list:&str, etag:&str) -> bool {
+    list.split(',').map(str::trim).any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+// Parses a single "bytes=" range into an inclusive (start, end). Ok(None) means the header is
+// ignored and the whole file is sent, Err means the range can't be satisfied.
+fn parse_range(range:&str, length:u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None); // multipart ranges aren't supported, the full file is a valid answer
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Ok(None);
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        // suffix range, the last n bytes
+        let Ok(suffix) = end.parse::<u64>() else {
+            return Ok(None);
+        };
+        if suffix == 0 || length == 0 {
+            return Err(());
+        }
+        return Ok(Some((length.saturating_sub(suffix), length - 1)));
+    }
+
+    let Ok(start) = start.parse::<u64>() else {
+        return Ok(None);
+    };
+    let end = if end.is_empty() {
+        length.saturating_sub(1)
+    } else {
+        match end.parse::<u64>() {
+            Ok(end) if end >= start => end.min(length.saturating_sub(1)),
+            _ => return Ok(None)
+        }
+    };
+    if start >= length {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+// If-Range only keeps the range when the client's copy is still the current file
+fn requested_range(range:Option<String>, if_range:Option<String>, etag:&str, length:u64) -> Result<Option<(u64, u64)>, ()> {
+    match range {
+        Some(_) if if_range.is_some_and(|if_range| if_range.trim() != etag) => Ok(None),
+        Some(range) => parse_range(&range, length),
+        None => Ok(None)
+    }
+}
+
+fn error(status_code:i32, message:&str) -> Response {
+    create_response_with_type(status_code, "text/plain", message)
+}
+
+// GET|HEAD /artifacts/<path> from the store at root, HEAD gets the same response without the
+// body when it's written
+pub fn handle_artifact(request:&HttpRequest, root:&Path) -> Response {
+    let Some(relative) = request.path.strip_prefix(ARTIFACTS_ROUTE) else {
+        return error(404, "artifact not found");
+    };
+    // decoded before it's checked, an encoded ".." is rejected like a plain one
+    let relative = query::percent_decode_path(relative.split('#').next().unwrap_or(""));
+    let Some(path) = resolve(root, &relative) else {
+        return error(404, "artifact not found");
+    };
+    let (mut file, metadata) = match File::open(&path).and_then(|file| file.metadata().map(|metadata| (file, metadata))) {
+        Ok((file, metadata)) if metadata.is_file() => (file, metadata),
+        _ => return error(404, "artifact not found")
+    };
+
+    let length = metadata.len();
+    let etag = etag(&metadata);
+    let mut headers = vec![
+        (String::from("ETag"), etag.clone()),
+        (String::from("Accept-Ranges"), String::from("bytes")),
+    ];
+
+    if request.header("If-None-Match").is_some_and(|list| etag_matches(&list, &etag)) {
+        return Response{ status_code:304, headers, body:Body::Bytes(vec![]) };
+    }
+
+    let (status_code, start, end) = match requested_range(request.header("Range"), request.header("If-Range"), &etag, length) {
+        Ok(Some((start, end))) => (206, start, end),
+        Ok(None) => (200, 0, length.saturating_sub(1)),
+        Err(_) => {
+            let headers = vec![(String::from("Content-Range"), format!("bytes */{length}"))];
+            return Response{ status_code:416, headers, body:Body::Bytes(vec![]) };
+        }
+    };
+    let content_length = if length == 0 { 0 } else { end - start + 1 };
+
+    let content_type = content_type(&path);
+    headers.insert(0, (String::from("Content-Type"), String::from(content_type)));
+    if status_code == 206 {
+        headers.push((String::from("Content-Range"), format!("bytes {start}-{end}/{length}")));
+    }
+
+    // a small text file is read into memory, so it can be compressed. HEAD reads it as well, its
+    // Content-Length and Content-Encoding have to be those of the GET.
+    if status_code == 200 && length <= COMPRESSION_MAX_SIZE && !content_type.ends_with("octet-stream") {
+        let mut contents = Vec::new();
+        match file.read_to_end(&mut contents) {
+            Ok(read) if read as u64 == length => {
+                println!("Serving {} ({} bytes)", path.display(), length);
+                return Response{ status_code, headers, body:Body::Bytes(contents) };
+            },
+            Ok(_) => println!("Artifact {} changed while reading it", path.display()),
+            Err(err) => println!("Failed to read artifact {}: {}", path.display(), err)
+        }
+        return error(500, "failed to read artifact");
+    }
+
+    println!("Serving bytes {}-{} of {} from {}", start, end, length, path.display());
+    Response{ status_code, headers, body:Body::File{ file, start, length:content_length } }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_and_open_ended_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some((0, 99))));
+        assert_eq!(parse_range("bytes=500-", 1000), Ok(Some((500, 999))));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Ok(Some((900, 999))));
+        assert_eq!(parse_range("bytes=999-999", 1000), Ok(Some((999, 999))));
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(parse_range("bytes=-100", 1000), Ok(Some((900, 999))));
+        assert_eq!(parse_range("bytes=-5000", 1000), Ok(Some((0, 999))));
+        assert_eq!(parse_range("bytes=-0", 1000), Err(()));
+        assert_eq!(parse_range("bytes=-10", 0), Err(()));
+    }
+
+    // Err is answered with 416
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Err(()));
+        assert_eq!(parse_range("bytes=2000-3000", 1000), Err(()));
+        assert_eq!(parse_range("bytes=0-", 0), Err(()));
+    }
+
+    #[test]
+    fn ignored_ranges() {
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), Ok(None));
+        assert_eq!(parse_range("items=0-9", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=9-0", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=a-9", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=-a", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=5", 1000), Ok(None));
+    }
+
+    #[test]
+    fn if_range() {
+        let range = || Some(String::from("bytes=10-19"));
+        assert_eq!(requested_range(range(), None, "\"a\"", 100), Ok(Some((10, 19))));
+        assert_eq!(requested_range(range(), Some(String::from(" \"a\" ")), "\"a\"", 100), Ok(Some((10, 19))));
+        assert_eq!(requested_range(range(), Some(String::from("\"b\"")), "\"a\"", 100), Ok(None));
+        assert_eq!(requested_range(None, Some(String::from("\"a\"")), "\"a\"", 100), Ok(None));
+        // a stale copy gets the whole file, not a 416
+        assert_eq!(requested_range(Some(String::from("bytes=500-")), Some(String::from("\"b\"")), "\"a\"", 100), Ok(None));
+    }
+
+    #[test]
+    fn etag_lists() {
+        assert!(etag_matches("\"a\"", "\"a\""));
+        assert!(etag_matches("W/\"a\"", "\"a\""));
+        assert!(etag_matches("\"b\", W/\"a\"", "\"a\""));
+        assert!(etag_matches("*", "\"a\""));
+        assert!(!etag_matches("\"b\", \"c\"", "\"a\""));
+        assert!(!etag_matches("", "\"a\""));
+    }
+}
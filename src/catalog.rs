@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::{fs, io};
+use std::path::Path;
+use serde::Deserialize;
+use crate::version::{load_versions, Versions};
+
+// A product's catalogs: versions.json for the product itself and one catalog per bundled
+// component (CDM, dictionaries, extensions) in the components directory, named <appid>.json.
+pub const COMPONENTS_DIR:&str = "catalogs";
+
+pub struct AppCatalog{
+    pub path:String, // file the catalog is loaded from and saved back to
+    pub versions:Versions,
+}
+
+pub struct Catalog{
+    pub appid:String, // Omaha app id of the product itself
+    pub main:AppCatalog,
+    pub apps:HashMap<String, AppCatalog>, // components keyed by app id
+}
+
+// Omaha app ids are GUIDs, they are compared without braces and case
+fn same_appid(a:&str, b:&str) -> bool {
+    let trim = |appid:&str| appid.trim().trim_matches(|c| c == '{' || c == '}').to_string();
+    trim(a).eq_ignore_ascii_case(&trim(b))
+}
+
+impl Catalog{
+    // The catalog an Omaha app id is answered from: the product's own app id gets the product,
+    // a component's app id the component, any other app id None
+    pub fn app_versions(&self, appid:&str) -> Option<&Versions> {
+        if same_appid(appid, &self.appid) {
+            return Some(&self.main.versions);
+        }
+        self.component(appid).map(|component| &component.versions)
+    }
+
+    // A component by app id, matched like Omaha app ids wherever the request comes from
+    pub fn component(&self, appid:&str) -> Option<&AppCatalog> {
+        self.apps
+            .iter()
+            .find(|(component, _)| same_appid(appid, component))
+            .map(|(_, component)| component)
+    }
+
+    // An empty app id names the product itself
+    pub fn app_mut(&mut self, appid:&str) -> Option<&mut AppCatalog> {
+        if appid.is_empty() {
+            return Some(&mut self.main);
+        }
+        self.apps
+            .iter_mut()
+            .find(|(component, _)| same_appid(appid, component))
+            .map(|(_, component)| component)
+    }
+}
+
+fn load_app(path:&Path) -> Result<AppCatalog, String> {
+    let path = path.to_string_lossy().to_string();
+    let versions = load_versions(&path)?;
+    Ok(AppCatalog{ path, versions })
+}
+
+// A missing components directory just means the product has no components
+pub fn load_catalog(appid:&str, versions_path:&str, components_dir:&str) -> Result<Catalog, String> {
+    let main = load_app(Path::new(versions_path))?;
+    let mut apps = HashMap::new();
+
+    if let Ok(entries) = fs::read_dir(components_dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            let Some(appid) = path.file_stem().map(|stem| stem.to_string_lossy().to_string()) else {
+                continue;
+            };
+            apps.insert(appid, load_app(&path)?);
+        }
+    }
+
+    Ok(Catalog{ appid:String::from(appid), main, apps })
+}
+
+// products.json lists every product hosted by this server, the first entry is the default product
+// of the admin endpoints. Without it the server hosts a single product from versions.json, a
+// products.json that exists but can't be read is an error.
+pub const PRODUCTS_PATH:&str = "products.json";
+pub const DEFAULT_PRODUCT:&str = "hypertrail";
+
+#[derive(Deserialize)]
+struct ProductConfig{
+    name:String, // matched against Request.updater
+    versions:String, // catalog of the product
+    #[serde(default)]
+    components:String, // components directory, no components when empty
+    #[serde(default)]
+    appid:String, // Omaha app id of the product, the name when empty
+}
+
+pub struct Products{
+    pub default:String,
+    pub catalogs:HashMap<String, Catalog>, // keyed by product name
+    sources:[String; 3], // products path, versions path and components directory they were loaded from
+}
+
+impl Products{
+    pub fn get(&self, name:&str) -> Option<&Catalog> {
+        self.catalogs.get(name)
+    }
+
+    // The product an Omaha app id belongs to, as the product itself or as one of its components
+    pub fn find_app(&self, appid:&str) -> Option<&Catalog> {
+        self.catalogs.values().find(|catalog| catalog.app_versions(appid).is_some())
+    }
+
+    // An empty name is the default product
+    pub fn get_mut(&mut self, name:&str) -> Option<&mut Catalog> {
+        let name = if name.is_empty() { self.default.as_str() } else { name };
+        self.catalogs.get_mut(name)
+    }
+
+    // Loads every catalog again from where they were loaded, nothing changes when one fails
+    pub fn reload(&mut self) -> Result<(), String> {
+        let [products_path, versions_path, components_dir] = &self.sources;
+        *self = load_products(products_path, versions_path, components_dir)?;
+        Ok(())
+    }
+}
+
+pub fn load_products(products_path:&str, versions_path:&str, components_dir:&str) -> Result<Products, String> {
+    let configs = match fs::read_to_string(products_path) {
+        Ok(contents) => serde_json::from_str::<Vec<ProductConfig>>(&contents).map_err(|err| format!("failed to parse {products_path}: {err}"))?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            vec![ProductConfig{ name:String::from(DEFAULT_PRODUCT), versions:String::from(versions_path), components:String::from(components_dir), appid:String::new() }]
+        },
+        Err(err) => return Err(format!("failed to read {products_path}: {err}"))
+    };
+    let Some(default) = configs.first().map(|config| config.name.clone()) else {
+        return Err(format!("{products_path} lists no products"));
+    };
+
+    let mut catalogs = HashMap::new();
+    for config in configs {
+        if catalogs.contains_key(&config.name) {
+            return Err(format!("product {} is listed twice", config.name));
+        }
+        let appid = if config.appid.is_empty() { &config.name } else { &config.appid };
+        let catalog = load_catalog(appid, &config.versions, &config.components)?;
+        catalogs.insert(config.name, catalog);
+    }
+    let sources = [String::from(products_path), String::from(versions_path), String::from(components_dir)];
+    Ok(Products{ default, catalogs, sources })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSIONS:&str = r#"{"stable":[], "beta":[], "dev":[], "canary":[], "extended":[]}"#;
+
+    #[test]
+    fn products_file_that_cannot_be_read() {
+        let dir = std::env::temp_dir().join(format!("updateserver-catalog-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let versions_path = dir.join("versions.json");
+        fs::write(&versions_path, VERSIONS).unwrap();
+        let (versions_path, components_dir) = (versions_path.to_string_lossy(), dir.join("catalogs").to_string_lossy().to_string());
+
+        // no products.json, the default product from versions.json
+        let missing = dir.join("products.json");
+        let products = load_products(&missing.to_string_lossy(), &versions_path, &components_dir).unwrap();
+        assert_eq!(products.default, DEFAULT_PRODUCT);
+
+        // a directory in its place is a broken setup, not a missing file
+        let err = load_products(&dir.to_string_lossy(), &versions_path, &components_dir).err().unwrap();
+        assert!(err.starts_with("failed to read"), "{err}");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,226 @@
+use std::env;
+use std::io::prelude::*;
+use flate2::write::GzEncoder;
+use crate::http::{add_vary, Body, Response};
+
+// Content-Encoding of responses, negotiated from the request's Accept-Encoding. Only bodies of
+// at least UPDATESERVER_COMPRESSION_MIN_SIZE bytes (1024 by default, 0 turns compression off)
+// with a text-like Content-Type are compressed. Packages, patches and other binaries go out as
+// they are, they are compressed already. Signatures and CUP proofs cover the decoded body.
+const COMPRESSION_MIN_SIZE_VAR:&str = "UPDATESERVER_COMPRESSION_MIN_SIZE";
+pub const COMPRESSION_MIN_SIZE:usize = 1024;
+// Larger artifacts are streamed from disk as they are instead of being compressed in memory
+pub const COMPRESSION_MAX_SIZE:u64 = 4 * 1024 * 1024;
+const COMPRESSIBLE_TYPES:[&str; 6] = ["text/", "application/json", "application/xml", "application/javascript", "image/svg+xml", "application/x-protobuf"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coding{
+    Zstd,
+    Brotli,
+    Gzip,
+}
+
+impl Coding{
+    fn name(&self) -> &'static str {
+        match self {
+            Coding::Zstd => "zstd",
+            Coding::Brotli => "br",
+            Coding::Gzip => "gzip",
+        }
+    }
+}
+
+// In order of preference when the client weighs them the same
+const CODINGS:[Coding; 3] = [Coding::Zstd, Coding::Brotli, Coding::Gzip];
+
+pub fn min_size() -> Result<usize, String> {
+    match env::var(COMPRESSION_MIN_SIZE_VAR) {
+        Ok(size) if !size.is_empty() => size.parse::<usize>().map_err(|_| format!("invalid {COMPRESSION_MIN_SIZE_VAR} {size}")),
+        _ => Ok(COMPRESSION_MIN_SIZE)
+    }
+}
+
+// Best coding the client accepts, None for identity
+pub fn negotiate(accept_encoding:Option<&str>) -> Option<Coding> {
+    let accept_encoding = accept_encoding?;
+    let mut best:Option<(f32, Coding)> = None;
+    for coding in CODINGS {
+        let mut quality = None;
+        for item in accept_encoding.split(',') {
+            let mut parts = item.split(';');
+            let name = parts.next().unwrap_or("").trim();
+            let item_quality = parts
+                .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                .find_map(|quality| quality.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if name.eq_ignore_ascii_case(coding.name()) {
+                quality = Some(item_quality);
+                break;
+            }
+            if name == "*" {
+                quality = Some(item_quality);
+            }
+        }
+        if let Some(quality) = quality.filter(|quality| *quality > 0.0) {
+            if best.is_none_or(|(best_quality, _)| quality > best_quality) {
+                best = Some((quality, coding));
+            }
+        }
+    }
+    best.map(|(_, coding)| coding)
+}
+
+fn compressible(content_type:&str) -> bool {
+    let content_type = content_type.trim().to_ascii_lowercase();
+    COMPRESSIBLE_TYPES.iter().any(|compressible| content_type.starts_with(compressible))
+}
+
+fn encode(coding:Coding, body:&[u8]) -> std::io::Result<Vec<u8>> {
+    match coding {
+        Coding::Zstd => zstd::encode_all(body, 3),
+        Coding::Brotli => {
+            let mut compressed = vec![];
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 5, 22);
+                encoder.write_all(body)?;
+            }
+            Ok(compressed)
+        },
+        Coding::Gzip => {
+            let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+    }
+}
+
+// Compresses the body of a response held in memory, None is identity. Responses are left as
+// they are when the type doesn't compress, the body is a partial or streamed file, too small,
+// or nothing is gained. Every response that could have been compressed carries
+// Vary: Accept-Encoding, compressed or not, so caches don't hand one client's encoding to another.
+pub fn compress(mut response:Response, coding:Option<Coding>, min_size:usize) -> Response {
+    let negotiable = min_size > 0
+        && response.status_code != 206
+        && matches!(response.body, Body::Bytes(_))
+        && response.header("Content-Encoding").is_none()
+        && response.header("Content-Type").is_some_and(compressible);
+    if !negotiable {
+        return response;
+    }
+    add_vary(&mut response.headers, "Accept-Encoding");
+
+    let (Some(coding), Body::Bytes(body)) = (coding, &response.body) else {
+        return response;
+    };
+    if body.len() < min_size {
+        return response;
+    }
+    let Some(compressed) = encode(coding, body).ok().filter(|compressed| compressed.len() < body.len()) else {
+        return response;
+    };
+
+    let mut headers = response.headers.into_iter().map(|(name, value)| {
+        if name.eq_ignore_ascii_case("ETag") {
+            // the encoded bytes differ, but they stand for the same file
+            let etag = format!("W/{}", value.trim_start_matches("W/"));
+            (name, etag)
+        } else {
+            (name, value)
+        }
+    }).collect::<Vec<(String, String)>>();
+    headers.push((String::from("Content-Encoding"), String::from(coding.name())));
+    Response{ status_code:response.status_code, headers, body:Body::Bytes(compressed) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::create_response_with_type;
+
+    #[test]
+    fn quality_values() {
+        assert_eq!(negotiate(Some("gzip;q=0.5, br;q=0.8")), Some(Coding::Brotli));
+        assert_eq!(negotiate(Some("gzip, zstd;q=0.1")), Some(Coding::Gzip));
+        assert_eq!(negotiate(Some("GZIP; q=1.0")), Some(Coding::Gzip));
+        // the same weight goes to the preferred coding
+        assert_eq!(negotiate(Some("gzip, br")), Some(Coding::Brotli));
+        assert_eq!(negotiate(Some("*")), Some(Coding::Zstd));
+        assert_eq!(negotiate(Some("zstd;q=0, *;q=0.5")), Some(Coding::Brotli));
+        assert_eq!(negotiate(Some("deflate, compress")), None);
+        assert_eq!(negotiate(Some("")), None);
+        assert_eq!(negotiate(None), None);
+    }
+
+    // identity is what the server falls back to, refusing it doesn't make any coding acceptable
+    #[test]
+    fn refused_codings() {
+        assert_eq!(negotiate(Some("identity;q=0")), None);
+        assert_eq!(negotiate(Some("gzip, identity;q=0")), Some(Coding::Gzip));
+        assert_eq!(negotiate(Some("gzip;q=0, br;q=0")), None);
+        assert_eq!(negotiate(Some("*;q=0")), None);
+        assert_eq!(negotiate(Some("gzip;q=0, *")), Some(Coding::Zstd));
+    }
+
+    fn json(size:usize) -> Response {
+        create_response_with_type(200, "application/json", &"a".repeat(size))
+    }
+
+    fn decoded(response:&Response) -> Vec<u8> {
+        let Body::Bytes(body) = &response.body else {
+            panic!("the body isn't in memory");
+        };
+        let mut decoded = vec![];
+        flate2::read::GzDecoder::new(&body[..]).read_to_end(&mut decoded).unwrap();
+        decoded
+    }
+
+    #[test]
+    fn minimum_size() {
+        let response = compress(json(99), Some(Coding::Gzip), 100);
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+
+        let response = compress(json(100), Some(Coding::Gzip), 100);
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(decoded(&response), "a".repeat(100).into_bytes());
+
+        // 0 turns compression off, nothing depends on Accept-Encoding then
+        let response = compress(json(100), Some(Coding::Gzip), 0);
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), None);
+    }
+
+    #[test]
+    fn vary_without_compression() {
+        let response = compress(json(2000), None, 100);
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+
+        let mut response = json(2000);
+        response.headers.push((String::from("Vary"), String::from("Accept")));
+        let response = compress(response, Some(Coding::Zstd), 100);
+        assert_eq!(response.header("Vary"), Some("Accept, Accept-Encoding"));
+
+        // types that are never compressed and partial content don't vary
+        let response = compress(create_response_with_type(200, "application/octet-stream", &"a".repeat(2000)), Some(Coding::Gzip), 100);
+        assert_eq!(response.header("Vary"), None);
+        let mut response = json(2000);
+        response.status_code = 206;
+        assert_eq!(compress(response, Some(Coding::Gzip), 100).header("Vary"), None);
+    }
+
+    #[test]
+    fn compressed_etags_are_weak() {
+        for etag in ["\"abc\"", "W/\"abc\""] {
+            let mut response = json(2000);
+            response.headers.push((String::from("ETag"), String::from(etag)));
+            let response = compress(response, Some(Coding::Gzip), 100);
+            assert_eq!(response.header("ETag"), Some("W/\"abc\""));
+        }
+
+        let mut response = json(10);
+        response.headers.push((String::from("ETag"), String::from("\"abc\"")));
+        assert_eq!(compress(response, Some(Coding::Gzip), 100).header("ETag"), Some("\"abc\""));
+    }
+}
@@ -0,0 +1,187 @@
+use std::cell::RefCell;
+use std::io::{self, prelude::*};
+use std::net::{Shutdown, TcpStream};
+use std::time::{Duration, Instant};
+use rustls::{ServerConnection, StreamOwned};
+
+// How long and how much a closing connection reads of what the client still sends
+const LINGER_TIMEOUT:Duration = Duration::from_secs(1);
+const LINGER_MAX:u64 = 1024 * 1024;
+
+// A client connection, plain TCP or TLS. Requests are read from and responses written to
+// &Connection the same way as &TcpStream, the RefCell gives the TLS state the mutable access it needs.
+enum Transport{
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+pub struct Connection{
+    transport:RefCell<Transport>,
+}
+
+impl Connection{
+    pub fn plain(stream:TcpStream) -> Connection {
+        Connection{ transport:RefCell::new(Transport::Plain(stream)) }
+    }
+
+    pub fn tls(connection:ServerConnection, stream:TcpStream) -> Connection {
+        Connection{ transport:RefCell::new(Transport::Tls(Box::new(StreamOwned::new(connection, stream)))) }
+    }
+
+    // Reads fail with WouldBlock/TimedOut once the client stays quiet this long
+    pub fn set_read_timeout(&self, timeout:Option<Duration>) -> io::Result<()> {
+        match &*self.transport.borrow() {
+            Transport::Plain(stream) => stream.set_read_timeout(timeout),
+            Transport::Tls(stream) => stream.sock.set_read_timeout(timeout)
+        }
+    }
+
+    // Sends whatever is still buffered and, for TLS, the close_notify alert. Request bytes
+    // still unread (e.g the body of a refused request) would make the close a reset that can
+    // discard the response before the client reads it, so they are read and dropped first.
+    pub fn close(&self){
+        let mut transport = self.transport.borrow_mut();
+        let result = match &mut *transport {
+            Transport::Plain(stream) => stream.flush(),
+            Transport::Tls(stream) => {
+                stream.conn.send_close_notify();
+                stream.flush()
+            }
+        };
+        if let Err(err) = result {
+            println!("Failed to close connection: {}", err);
+            return;
+        }
+        let socket = match &*transport {
+            Transport::Plain(stream) => stream,
+            Transport::Tls(stream) => &stream.sock
+        };
+        if socket.shutdown(Shutdown::Write).is_ok() && socket.set_read_timeout(Some(LINGER_TIMEOUT)).is_ok() {
+            let _ = io::copy(&mut socket.take(LINGER_MAX), &mut io::sink());
+        }
+    }
+}
+
+impl Read for &Connection{
+    fn read(&mut self, buf:&mut [u8]) -> io::Result<usize> {
+        match &mut *self.transport.borrow_mut() {
+            Transport::Plain(stream) => stream.read(buf),
+            Transport::Tls(stream) => stream.read(buf)
+        }
+    }
+}
+
+// Reads a connection for the requests on it. Waiting for a request only has the idle timeout,
+// once one has started it also has to arrive before its deadline, so a client can't hold its
+// thread by sending a byte now and then.
+pub struct RequestReader<'a>{
+    connection:&'a Connection,
+    idle_timeout:Duration,
+    deadline:Option<Instant>,
+}
+
+impl RequestReader<'_>{
+    pub fn new(connection:&Connection, idle_timeout:Duration) -> RequestReader<'_> {
+        RequestReader{ connection, idle_timeout, deadline:None }
+    }
+
+    // Between requests, only the idle timeout applies
+    pub fn wait(&mut self){
+        self.deadline = None;
+    }
+
+    // The request that has started must be read within timeout
+    pub fn start(&mut self, timeout:Duration){
+        self.deadline = Some(Instant::now() + timeout);
+    }
+}
+
+impl Read for RequestReader<'_>{
+    fn read(&mut self, buf:&mut [u8]) -> io::Result<usize> {
+        let timeout = match self.deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "request not received in time"));
+                }
+                left.min(self.idle_timeout)
+            },
+            None => self.idle_timeout
+        };
+        self.connection.set_read_timeout(Some(timeout))?;
+        let mut connection = self.connection;
+        connection.read(buf)
+    }
+}
+
+impl Write for &Connection{
+    fn write(&mut self, buf:&[u8]) -> io::Result<usize> {
+        match &mut *self.transport.borrow_mut() {
+            Transport::Plain(stream) => stream.write(buf),
+            Transport::Tls(stream) => stream.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut *self.transport.borrow_mut() {
+            Transport::Plain(stream) => stream.flush(),
+            Transport::Tls(stream) => stream.flush()
+        }
+    }
+}
+
+// Persistent connections: a client may send further requests, pipelined or not, on the same
+// connection until it sends Connection: close, stays idle for UPDATESERVER_KEEPALIVE_TIMEOUT
+// seconds or reaches UPDATESERVER_KEEPALIVE_MAX requests. Every connection has its own thread,
+// at most UPDATESERVER_MAX_CONNECTIONS at a time, so a quiet client only holds its own thread.
+// A request that has started must arrive within UPDATESERVER_REQUEST_TIMEOUT seconds.
+const KEEPALIVE_TIMEOUT_VAR:&str = "UPDATESERVER_KEEPALIVE_TIMEOUT";
+const KEEPALIVE_MAX_VAR:&str = "UPDATESERVER_KEEPALIVE_MAX";
+const REQUEST_TIMEOUT_VAR:&str = "UPDATESERVER_REQUEST_TIMEOUT";
+const MAX_CONNECTIONS_VAR:&str = "UPDATESERVER_MAX_CONNECTIONS";
+const KEEPALIVE_TIMEOUT_SECS:u64 = 5;
+const KEEPALIVE_MAX:usize = 100;
+const REQUEST_TIMEOUT_SECS:u64 = 30;
+const MAX_CONNECTIONS:usize = 256;
+
+#[derive(Clone, Copy)]
+pub struct KeepAlive{
+    pub idle_timeout:Duration,
+    pub max_requests:usize, // 1 turns persistent connections off
+    pub request_timeout:Duration, // head and body of one request, from its first byte
+}
+
+fn var_or<T:std::str::FromStr>(name:&str, default:T) -> Result<T, String> {
+    match std::env::var(name) {
+        Ok(value) if !value.is_empty() => value.parse::<T>().map_err(|_| format!("invalid {name} {value}")),
+        _ => Ok(default)
+    }
+}
+
+pub fn keep_alive() -> Result<KeepAlive, String> {
+    let idle_timeout = var_or(KEEPALIVE_TIMEOUT_VAR, KEEPALIVE_TIMEOUT_SECS)?;
+    let max_requests = var_or(KEEPALIVE_MAX_VAR, KEEPALIVE_MAX)?;
+    let request_timeout = var_or(REQUEST_TIMEOUT_VAR, REQUEST_TIMEOUT_SECS)?;
+    if idle_timeout == 0 || max_requests == 0 || request_timeout == 0 {
+        return Err(format!("{KEEPALIVE_TIMEOUT_VAR}, {KEEPALIVE_MAX_VAR} and {REQUEST_TIMEOUT_VAR} must be at least 1"));
+    }
+    Ok(KeepAlive{ idle_timeout:Duration::from_secs(idle_timeout), max_requests, request_timeout:Duration::from_secs(request_timeout) })
+}
+
+pub fn max_connections() -> Result<usize, String> {
+    let max_connections = var_or(MAX_CONNECTIONS_VAR, MAX_CONNECTIONS)?;
+    if max_connections == 0 {
+        return Err(format!("{MAX_CONNECTIONS_VAR} must be at least 1"));
+    }
+    Ok(max_connections)
+}
+
+// Whether the client wants the connection kept open after this request
+pub fn wants_keep_alive(version:&str, connection_header:Option<&str>) -> bool {
+    let has = |token:&str| connection_header.is_some_and(|value| value.split(',').any(|option| option.trim().eq_ignore_ascii_case(token)));
+    if version == "HTTP/1.0" {
+        has("keep-alive")
+    } else {
+        !has("close")
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::mem;
+use std::time::{Duration, Instant};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+use p256::pkcs8::EncodePublicKey;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::signing;
+use crate::http::{create_response, create_response_with_type, Response};
+
+// Client Update Protocol (CUP-ECDSA) for the Omaha endpoints, as used by Chromium's updater.
+// Clients send cup2key=<key version>:<nonce> and cup2hreq=<hex sha256 of the request body> in
+// the query. The server answers with X-Cup-Server-Proof: <hex DER signature>:<hex request hash>,
+// an ECDSA P-256/SHA-256 signature over sha256(request hash || response hash || cup2key).
+// Nonces are remembered for at least an hour, a request that reuses one is rejected as a replay.
+pub const PROOF_HEADER:&str = "X-Cup-Server-Proof";
+const CUP_KEY_VAR:&str = "UPDATESERVER_CUP_KEY";
+const CUP_KEY_VERSION_VAR:&str = "UPDATESERVER_CUP_KEY_VERSION";
+const CUP_KEY_PATH:&str = "cup.key";
+const NONCE_TTL:Duration = Duration::from_secs(3600);
+const MAX_NONCES:usize = 100_000; // per set, a flood of requests rotates the sets early
+
+pub struct CupKey{
+    pub version:u32, // the version clients send in cup2key
+    pub key:SigningKey,
+}
+
+// Nonces seen in the current and the previous period. The current set becomes the previous one
+// when it is NONCE_TTL old or full, so a nonce is remembered for one to two periods (less only
+// under a flood of MAX_NONCES requests) and memory stays bounded without scanning every nonce.
+pub struct NonceCache{
+    current:HashSet<String>,
+    previous:HashSet<String>,
+    rotated:Instant, // when current was started
+}
+
+impl Default for NonceCache{
+    fn default() -> NonceCache {
+        NonceCache{ current:HashSet::new(), previous:HashSet::new(), rotated:Instant::now() }
+    }
+}
+
+impl NonceCache{
+    // Remembers the nonce, false when it was already used
+    fn insert(&mut self, nonce:&str, now:Instant) -> bool {
+        let age = now.duration_since(self.rotated);
+        if age >= NONCE_TTL * 2 {
+            self.previous.clear();
+            self.current.clear();
+            self.rotated = now;
+        } else if age >= NONCE_TTL || self.current.len() >= MAX_NONCES {
+            self.previous = mem::take(&mut self.current);
+            self.rotated = now;
+        }
+        if self.current.contains(nonce) || self.previous.contains(nonce) {
+            return false;
+        }
+        self.current.insert(String::from(nonce));
+        true
+    }
+}
+
+pub struct CupRequest<'a>{
+    key:&'a CupKey,
+    cup2key:String, // "<key version>:<nonce>" exactly as sent
+    request_hash:[u8; 32],
+}
+
+#[derive(Serialize, Deserialize)]
+struct CupKeyResponse{
+    keyversion:u32,
+    publickey:String, // DER SubjectPublicKeyInfo, base64
+}
+
+fn key_version() -> Result<u32, String> {
+    match env::var(CUP_KEY_VERSION_VAR) {
+        Ok(version) if !version.is_empty() => version.parse::<u32>().map_err(|_| format!("invalid {CUP_KEY_VERSION_VAR} {version}")),
+        _ => Ok(1)
+    }
+}
+
+pub fn key_path() -> String {
+    match env::var(CUP_KEY_VAR) {
+        Ok(path) if !path.is_empty() => path,
+        _ => String::from(CUP_KEY_PATH)
+    }
+}
+
+fn generate_key(path:&str) -> Result<SigningKey, String> {
+    // not every 32 byte string is a valid P-256 scalar, try again in the rare case it isn't
+    let key = loop {
+        let mut secret = [0u8; 32];
+        getrandom::getrandom(&mut secret).map_err(|err| format!("failed to generate a CUP key: {err}"))?;
+        if let Ok(key) = SigningKey::from_slice(&secret) {
+            break key;
+        }
+    };
+    signing::create_key_file(path, &hex::encode(key.to_bytes()))?;
+    println!("Created a new CUP key in {}", path);
+    Ok(key)
+}
+
+fn read_key(path:&str) -> Result<SigningKey, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return generate_key(path),
+        Err(err) => return Err(format!("failed to read {path}: {err}"))
+    };
+    let secret = hex::decode(contents.trim()).map_err(|err| format!("invalid CUP key in {path}: {err}"))?;
+    SigningKey::from_slice(&secret).map_err(|_| format!("CUP key in {path} is not a P-256 private key"))
+}
+
+pub fn load_key(path:&str) -> Result<CupKey, String> {
+    let version = key_version()?;
+    let key = read_key(path)?;
+    println!("Answering CUP requests with key version {}", version);
+    Ok(CupKey{ version, key })
+}
+
+fn query_value<'a>(query:&'a str, name:&str) -> Option<&'a str> {
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        if key == name { Some(value) } else { None }
+    })
+}
+
+fn reject(message:&str) -> Response {
+    println!("Rejected CUP request: {}", message);
+    create_response_with_type(400, "text/plain", message)
+}
+
+// Ok(None) for requests without CUP parameters. Err carries the rejection to send back.
+pub fn check_request<'a>(query:&str, body:&str, cup_key:Option<&'a CupKey>, nonces:&mut NonceCache) -> Result<Option<CupRequest<'a>>, Response> {
+    let Some(cup2key) = query_value(query, "cup2key") else {
+        return Ok(None);
+    };
+    let Some(cup_key) = cup_key else {
+        return Err(reject("CUP is not configured on this server"));
+    };
+
+    let Some((version, nonce)) = cup2key.split_once(':') else {
+        return Err(reject("cup2key must be <key version>:<nonce>"));
+    };
+    if version.parse::<u32>().ok() != Some(cup_key.version) {
+        return Err(reject(&format!("unknown CUP key version {version}")));
+    }
+    if nonce.is_empty() {
+        return Err(reject("missing CUP nonce"));
+    }
+
+    let request_hash:[u8; 32] = Sha256::digest(body.as_bytes()).into();
+    if let Some(hreq) = query_value(query, "cup2hreq") {
+        if !hreq.eq_ignore_ascii_case(&hex::encode(request_hash)) {
+            return Err(reject("cup2hreq doesn't match the request body"));
+        }
+    }
+    if !nonces.insert(nonce, Instant::now()) {
+        return Err(reject("CUP nonce was already used"));
+    }
+
+    Ok(Some(CupRequest{ key:cup_key, cup2key:String::from(cup2key), request_hash }))
+}
+
+// Value of the X-Cup-Server-Proof header for a response body
+pub fn proof(cup:&CupRequest, response_body:&[u8]) -> String {
+    let mut signed_message = Vec::with_capacity(64 + cup.cup2key.len());
+    signed_message.extend_from_slice(&cup.request_hash);
+    signed_message.extend_from_slice(&Sha256::digest(response_body));
+    signed_message.extend_from_slice(cup.cup2key.as_bytes());
+    let signature:Signature = cup.key.key.sign(&Sha256::digest(&signed_message));
+    format!("{}:{}", hex::encode(signature.to_der().as_bytes()), hex::encode(cup.request_hash))
+}
+
+// GET /cup/publickey
+pub fn handle_public_key(cup_key:Option<&CupKey>) -> Response {
+    let public_key = cup_key.and_then(|cup_key| {
+        let der = cup_key.key.verifying_key().to_public_key_der().ok()?;
+        Some(CupKeyResponse{ keyversion:cup_key.version, publickey:BASE64.encode(der.as_bytes()) })
+    });
+    match public_key {
+        Some(response_object) => create_response(200, &serde_json::to_string(&response_object).unwrap()),
+        None => create_response(404, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::{signature::Verifier, VerifyingKey};
+
+    fn cup_key() -> CupKey {
+        CupKey{ version:7, key:SigningKey::from_slice(&[7u8; 32]).unwrap() }
+    }
+
+    // Chromium's client_update_protocol/ecdsa.cc checks an ECDSA-SHA256 signature over
+    // sha256(request hash || sha256(response body) || cup2key)
+    #[test]
+    fn proof_verifies_like_chromium() {
+        let cup_key = cup_key();
+        let body = r#"<request protocol="3.0"/>"#;
+        let request_hash = Sha256::digest(body.as_bytes());
+        let query = format!("cup2key=7:nonce-1&cup2hreq={}", hex::encode(request_hash));
+        let cup = check_request(&query, body, Some(&cup_key), &mut NonceCache::default()).ok().flatten().unwrap();
+
+        let response_body = b"<response protocol=\"3.0\"/>";
+        let proof = proof(&cup, response_body);
+        let (signature, hash) = proof.split_once(':').unwrap();
+        assert_eq!(hash, hex::encode(request_hash));
+
+        let mut message = request_hash.to_vec();
+        message.extend_from_slice(&Sha256::digest(response_body));
+        message.extend_from_slice(b"7:nonce-1");
+        let message_hash = Sha256::digest(&message);
+        let signature = Signature::from_der(&hex::decode(signature).unwrap()).unwrap();
+        let verifying_key = VerifyingKey::from(&cup_key.key);
+        verifying_key.verify(&message_hash, &signature).unwrap();
+        assert!(verifying_key.verify(&Sha256::digest(b"another response"), &signature).is_err());
+    }
+
+    #[test]
+    fn replayed_nonces_are_rejected() {
+        let cup_key = cup_key();
+        let mut nonces = NonceCache::default();
+        assert!(matches!(check_request("cup2key=7:a", "", Some(&cup_key), &mut nonces), Ok(Some(_))));
+        assert!(check_request("cup2key=7:a", "", Some(&cup_key), &mut nonces).is_err());
+        assert!(matches!(check_request("cup2key=7:b", "", Some(&cup_key), &mut nonces), Ok(Some(_))));
+        assert!(check_request("cup2key=6:c", "", Some(&cup_key), &mut nonces).is_err());
+        assert!(check_request("cup2key=7:c", "", None, &mut nonces).is_err());
+        assert!(matches!(check_request("", "", None, &mut nonces), Ok(None)));
+    }
+
+    #[test]
+    fn nonces_are_remembered_for_one_to_two_periods() {
+        let start = Instant::now();
+        let mut nonces = NonceCache{ rotated:start, ..Default::default() };
+        assert!(nonces.insert("a", start));
+        assert!(!nonces.insert("a", start + NONCE_TTL / 2));
+        // rotated into the previous set, still known
+        assert!(!nonces.insert("a", start + NONCE_TTL + NONCE_TTL / 2));
+        assert!(nonces.insert("a", start + NONCE_TTL * 3));
+    }
+
+    #[test]
+    fn nonce_sets_are_bounded() {
+        let now = Instant::now();
+        let mut nonces = NonceCache::default();
+        for nonce in 0..MAX_NONCES * 3 {
+            assert!(nonces.insert(&nonce.to_string(), now));
+        }
+        assert!(nonces.current.len() <= MAX_NONCES && nonces.previous.len() <= MAX_NONCES);
+        assert!(!nonces.insert(&(MAX_NONCES * 3 - 1).to_string(), now));
+    }
+}
@@ -0,0 +1,33 @@
+use serde::{Serialize, Deserialize};
+use crate::{os, Architecture, Platform, Request};
+use crate::version::{parse_number, Version};
+
+// Binary patches from an older version to a catalog entry. Clients that run the `from` version
+// get the patch next to the full package and fall back to the full package when the patch
+// doesn't apply (reported through /status with "delta":true).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Delta{
+    pub from:String, // version the patch applies to
+    pub url:String,
+    pub sha256:String, // hex digest of the patch file
+    #[serde(default)]
+    pub size:u64, // size of the patch file in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform:Option<Platform>, // platform of the builds the patch is made from, any platform when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch:Option<Architecture>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub target_sha256:String, // hex digest of the full package the patch rebuilds
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub format:String, // how the patch is applied, "zstd-patch" for generated patches
+}
+
+// The patch for the version the client runs, if the catalog entry has one for its platform
+pub fn find_delta<'a>(version:&'a Version, request:&Request) -> Option<&'a Delta> {
+    let current = parse_number(&request.version)?;
+    version.deltas.iter().find(|delta| {
+        parse_number(&delta.from) == Some(current)
+            && delta.platform.as_ref().is_none_or(|platform| *platform == os::platform(&request.os))
+            && delta.arch.as_ref().is_none_or(|arch| os::arch(&request.os).is_some_and(|client_arch| os::same_arch(arch, &client_arch)))
+    })
+}
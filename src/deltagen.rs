@@ -0,0 +1,586 @@
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use sha2::{Digest, Sha256};
+use crate::{os, Architecture, Channel, Platform, VERSIONS_PATH};
+use crate::artifacts::{self, ARTIFACTS_ROUTE};
+use crate::catalog::{load_products, AppCatalog, COMPONENTS_DIR, PRODUCTS_PATH};
+use crate::delta::Delta;
+use crate::version::{lock_versions, read_versions, save_versions, Version, Versions};
+
+// `updateserver delta [--product NAME] [--channel CHANNEL] [--history N]`
+// Builds zstd --patch-from deltas from the previous N versions (5 by default) to the newest
+// version of each channel, for every platform whose full packages are both in the artifact
+// store, in the catalog of the product and in those of its components.
+// `updateserver delta --from <version|artifact> --to <version|artifact>` builds the patches of
+// one pair instead, for every platform both have, or for the named artifacts only. Patches are written
+// next to the new package and recorded in the catalog with their digests. Clients apply them
+// with `zstd -d --long=31 --patch-from=<old package>`. A running server offers them once it
+// reloads its catalogs (SIGHUP or POST /admin/reload).
+pub const DELTA_FORMAT:&str = "zstd-patch";
+const DELTA_HISTORY:usize = 5;
+const DELTA_LEVEL:i32 = 19;
+const MAX_WINDOW_LOG:u32 = 31;
+const CHANNELS:[Channel; 5] = [Channel::Stable, Channel::Beta, Channel::Dev, Channel::Canary, Channel::Extended];
+const USAGE:&str = "usage: updateserver delta [--product NAME] [--channel CHANNEL] [--history N]\n\
+    patches the newest version of each channel of the product and of its components\n\
+    usage: updateserver delta [--product NAME] [--channel CHANNEL] --from <version|artifact> --to <version|artifact>\n\
+    patches one version from another, artifacts are given by their path under the artifact store";
+
+struct Options{
+    product:String, // empty for the default product
+    channels:Vec<Channel>,
+    history:usize, // number of previous versions that get a patch
+    pair:Option<(String, String)>, // explicit from and to, instead of the newest version and its history
+}
+
+// A full package of a version that lives in the artifact store
+struct Package{
+    platform:Option<Platform>, // None when the version has no per-platform builds
+    arch:Option<Architecture>,
+    url:String,
+    path:PathBuf,
+}
+
+struct Job{
+    target:String, // number of the new version
+    delta:Delta,
+    old_path:PathBuf,
+    new_path:PathBuf,
+    patch_path:PathBuf,
+}
+
+fn parse_channel(name:&str) -> Option<Channel> {
+    CHANNELS.iter().find(|channel| channel.to_string().eq_ignore_ascii_case(name)).cloned()
+}
+
+fn parse_options(args:&[String]) -> Result<Options, String> {
+    let mut options = Options{ product:String::new(), channels:CHANNELS.to_vec(), history:DELTA_HISTORY, pair:None };
+    let (mut from, mut to, mut with_history) = (None, None, false);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--product" => options.product = value()?.clone(),
+            "--channel" => {
+                let name = value()?;
+                options.channels = vec![parse_channel(name).ok_or(format!("unknown channel {name}"))?];
+            },
+            "--history" => {
+                let history = value()?;
+                options.history = history.parse::<usize>().map_err(|_| format!("invalid history {history}"))?;
+                with_history = true;
+            },
+            "--from" => from = Some(value()?.clone()),
+            "--to" => to = Some(value()?.clone()),
+            _ => return Err(format!("unknown option {arg}"))
+        }
+    }
+    options.pair = match (from, to) {
+        (Some(_), Some(_)) if with_history => return Err(String::from("--history doesn't go with --from and --to")),
+        (Some(from), Some(to)) => Some((from, to)),
+        (None, None) => None,
+        _ => return Err(String::from("--from and --to go together"))
+    };
+    Ok(options)
+}
+
+fn store_path(store:&Path, url:&str) -> Option<PathBuf> {
+    let (_, relative) = url.split_once(ARTIFACTS_ROUTE)?;
+    artifacts::resolve(store, relative).filter(|path| path.is_file())
+}
+
+fn packages(version:&Version, store:&Path) -> Vec<Package> {
+    if version.builds.is_empty() {
+        return version.mirror_set()
+            .into_iter()
+            .find_map(|mirror| store_path(store, &mirror.url).map(|path| Package{ platform:None, arch:None, url:mirror.url, path }))
+            .into_iter()
+            .collect();
+    }
+    version.builds
+        .iter()
+        .filter_map(|build| store_path(store, &build.server).map(|path| Package{
+            platform:Some(build.platform.clone()),
+            arch:Some(build.arch.clone()),
+            url:build.server.clone(),
+            path
+        }))
+        .collect()
+}
+
+fn same_target(a:&Package, platform:&Option<Platform>, arch:&Option<Architecture>) -> bool {
+    let same_arch = match (&a.arch, arch) {
+        (Some(a), Some(b)) => os::same_arch(a, b),
+        (None, None) => true,
+        _ => false
+    };
+    a.platform == *platform && same_arch
+}
+
+// Patches from the previous versions to the newest version of a channel that aren't in the catalog yet
+fn plan(channel:&[Version], history:usize, store:&Path) -> Vec<Job> {
+    let Some(target) = channel.iter().position(|version| !version.halted) else {
+        return vec![];
+    };
+    let new_version = &channel[target];
+    let new_packages = packages(new_version, store);
+
+    let mut jobs = vec![];
+    for old_version in channel.iter().skip(target + 1).take(history) {
+        jobs.extend(pair_jobs(old_version, &packages(old_version, store), new_version, &new_packages));
+    }
+    jobs
+}
+
+// The packages of a version a --from or --to value names: all of them for its version number,
+// or the one whose path under the artifact store (or file name) it is
+fn select(version:&Version, store:&Path, name:&str) -> Vec<Package> {
+    let packages = packages(version, store);
+    if version.matches(name) {
+        return packages;
+    }
+    let path = artifacts::resolve(store, name.trim_start_matches('/'));
+    packages
+        .into_iter()
+        .filter(|package| Some(&package.path) == path.as_ref() || package.path.file_name().is_some_and(|file| file.to_string_lossy() == name))
+        .collect()
+}
+
+// Patches from the --from to the --to version of a channel that aren't in the catalog yet.
+// The old version can come from any channel, the patch is recorded on the new one.
+fn plan_pair(channel:&[Version], catalog:&Versions, from:&str, to:&str, store:&Path) -> Vec<Job> {
+    let old = CHANNELS
+        .iter()
+        .flat_map(|channel| catalog.channel(channel))
+        .map(|version| (version, select(version, store, from)))
+        .find(|(_, packages)| !packages.is_empty());
+    let Some((old_version, old_packages)) = old else {
+        return vec![];
+    };
+    channel
+        .iter()
+        .map(|version| (version, select(version, store, to)))
+        .find(|(_, packages)| !packages.is_empty())
+        .map(|(new_version, new_packages)| pair_jobs(old_version, &old_packages, new_version, &new_packages))
+        .unwrap_or_default()
+}
+
+// A patch for every platform both versions have a package for, unless the catalog lists it already
+fn pair_jobs(old_version:&Version, old_packages:&[Package], new_version:&Version, new_packages:&[Package]) -> Vec<Job> {
+    let mut jobs = vec![];
+    if old_version.number() == new_version.number() {
+        return jobs;
+    }
+    for new_package in new_packages {
+        let exists = new_version.deltas.iter().any(|delta| {
+            delta.from == old_version.number() && same_target(new_package, &delta.platform, &delta.arch)
+        });
+        if exists {
+            continue;
+        }
+        let Some(old_package) = old_packages.iter().find(|old_package| same_target(old_package, &new_package.platform, &new_package.arch)) else {
+            continue;
+        };
+
+        let file_name = new_package.path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let patch_name = format!("{}.from-{}.patch", file_name, old_version.number());
+        let url = match new_package.url.rsplit_once('/') {
+            Some((base, _)) => format!("{base}/{patch_name}"),
+            None => patch_name.clone()
+        };
+        jobs.push(Job{
+            target:new_version.number(),
+            delta:Delta{
+                from:old_version.number(),
+                url,
+                sha256:String::new(),
+                size:0,
+                platform:new_package.platform.clone(),
+                arch:new_package.arch.clone(),
+                target_sha256:String::new(),
+                format:String::from(DELTA_FORMAT),
+            },
+            old_path:old_package.path.clone(),
+            new_path:new_package.path.clone(),
+            patch_path:new_package.path.with_file_name(patch_name),
+        });
+    }
+    jobs
+}
+
+// The match window has to reach back over the whole old package
+fn window_log(size:u64) -> u32 {
+    let log = u64::BITS - size.max(1).saturating_sub(1).leading_zeros();
+    log.clamp(10, MAX_WINDOW_LOG)
+}
+
+fn sha256_file(path:&PathBuf) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// Writes the patch and checks that it rebuilds the new package, fills in size and digests.
+// zstd needs the old package in memory as the prefix, the new one is streamed through the
+// encoder and hashed on the way.
+fn make_patch(job:&mut Job) -> io::Result<()> {
+    let old = fs::read(&job.old_path)?;
+    let new_size = fs::metadata(&job.new_path)?.len();
+
+    let mut encoder = zstd::stream::write::Encoder::with_ref_prefix(BufWriter::new(File::create(&job.patch_path)?), DELTA_LEVEL, &old)?;
+    encoder.long_distance_matching(true)?;
+    encoder.window_log(window_log((old.len() as u64).max(new_size)))?;
+    encoder.set_pledged_src_size(Some(new_size))?;
+    encoder.include_checksum(true)?;
+    let mut new = BufReader::new(File::open(&job.new_path)?);
+    let mut hasher = Sha256::new();
+    loop {
+        let chunk = new.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        hasher.update(chunk);
+        encoder.write_all(chunk)?;
+        let length = chunk.len();
+        new.consume(length);
+    }
+    encoder.finish()?.flush()?;
+    let target_sha256 = hex::encode(hasher.finalize());
+
+    let mut decoder = zstd::stream::read::Decoder::with_ref_prefix(BufReader::new(File::open(&job.patch_path)?), &old)?;
+    decoder.window_log_max(MAX_WINDOW_LOG)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut decoder, &mut hasher)?;
+    if hex::encode(hasher.finalize()) != target_sha256 {
+        let _ = fs::remove_file(&job.patch_path);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "patch doesn't rebuild the new package"));
+    }
+
+    job.delta.size = fs::metadata(&job.patch_path)?.len();
+    job.delta.sha256 = sha256_file(&job.patch_path)?;
+    job.delta.target_sha256 = target_sha256;
+    Ok(())
+}
+
+// Adds the patches to the catalog file under its lock. The file is read again, so what the
+// server's admin endpoints saved meanwhile is kept.
+fn record(path:&str, patches:Vec<(Channel, Job)>) -> Result<(), String> {
+    let _lock = lock_versions(path).map_err(|err| format!("failed to lock {path}: {err}"))?;
+    let mut versions = read_versions(path)?;
+    for (channel, job) in patches {
+        let Some(version) = versions.find_mut(&channel, &job.target) else {
+            println!("{} left {} meanwhile, its patch from {} isn't recorded", job.target, channel, job.delta.from);
+            continue;
+        };
+        if !version.deltas.iter().any(|delta| delta.url == job.delta.url) {
+            version.deltas.push(job.delta);
+        }
+    }
+    save_versions(path, &versions).map_err(|err| format!("failed to save {path}: {err}"))
+}
+
+// Patches of one catalog, returns how many were created and how many failed
+fn generate(name:&str, app:&AppCatalog, options:&Options, store:&Path) -> Result<(usize, usize), String> {
+    let mut patches = vec![];
+    let mut failed = 0;
+    for channel in &options.channels {
+        let jobs = match &options.pair {
+            Some((from, to)) => plan_pair(app.versions.channel(channel), &app.versions, from, to, store),
+            None => plan(app.versions.channel(channel), options.history, store)
+        };
+        for mut job in jobs {
+            match make_patch(&mut job) {
+                Ok(_) => {
+                    println!("{} {} {} -> {}: {} ({} bytes)", name, channel, job.delta.from, job.target, job.patch_path.display(), job.delta.size);
+                    patches.push((channel.clone(), job));
+                },
+                Err(err) => {
+                    println!("{} {} {} -> {}: failed: {}", name, channel, job.delta.from, job.target, err);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    let created = patches.len();
+    if created > 0 {
+        record(&app.path, patches)?;
+    }
+    Ok((created, failed))
+}
+
+// Entry point of the subcommand, returns the process exit code
+pub fn run(args:&[String]) -> i32 {
+    let options = match parse_options(args) {
+        Ok(options) => options,
+        Err(err) => {
+            println!("{err}");
+            println!("{USAGE}");
+            return 2;
+        }
+    };
+    let products = match load_products(PRODUCTS_PATH, VERSIONS_PATH, COMPONENTS_DIR) {
+        Ok(products) => products,
+        Err(err) => {
+            println!("Failed to load the catalogs: {err}");
+            return 1;
+        }
+    };
+    let product = if options.product.is_empty() { products.default.clone() } else { options.product.clone() };
+    let Some(catalog) = products.get(&product) else {
+        println!("unknown product {}", product);
+        return 1;
+    };
+
+    // the product first, then its components by app id
+    let mut apps = vec![(product.clone(), &catalog.main)];
+    let mut components = catalog.apps.iter().map(|(appid, app)| (appid.clone(), app)).collect::<Vec<(String, &AppCatalog)>>();
+    components.sort_by(|(a, _), (b, _)| a.cmp(b));
+    apps.extend(components);
+
+    let store = artifacts::artifacts_dir();
+    let mut created = 0;
+    let mut failed = 0;
+    for (name, app) in apps {
+        match generate(&name, app, &options, &store) {
+            Ok((app_created, app_failed)) => {
+                created += app_created;
+                failed += app_failed;
+            },
+            Err(err) => {
+                println!("{name}: {err}");
+                return 1;
+            }
+        }
+    }
+
+    println!("{created} deltas created, {failed} failed");
+    if let Some((from, to)) = options.pair.as_ref().filter(|_| created + failed == 0) {
+        println!("No patch from {from} to {to}: both need a full package in the artifact store for the same platform, and the catalog mustn't list the patch yet");
+        return 1;
+    }
+    if created > 0 {
+        println!("A running server offers them after `kill -HUP <pid>` or POST /admin/reload");
+    }
+    if failed > 0 { 1 } else { 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version::{load_versions, parse_versions};
+
+    // An artifact store with the given files, removed by the test when it's done
+    fn store(name:&str, files:&[(&str, &[u8])]) -> PathBuf {
+        let store = std::env::temp_dir().join(format!("updateserver-deltagen-{}-{name}", std::process::id()));
+        for (file, contents) in files {
+            let path = store.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn window_covers_the_old_package() {
+        assert_eq!(window_log(0), 10);
+        assert_eq!(window_log(1024), 10);
+        assert_eq!(window_log(1025), 11);
+        assert_eq!(window_log(1 << 20), 20);
+        assert_eq!(window_log((1 << 20) + 1), 21);
+        assert_eq!(window_log(u64::MAX), MAX_WINDOW_LOG);
+    }
+
+    #[test]
+    fn patches_to_the_newest_version() {
+        let store = store("plan", &[("pkg-1.2.bin", b"1.2"), ("pkg-1.1.bin", b"1.1"), ("pkg-1.0.bin", b"1.0"), ("pkg-0.8.bin", b"0.8")]);
+        // 1.3.0 is halted, 1.1.0 already has its patch, 0.9.0 isn't in the store and 0.8.0 is
+        // beyond the history
+        let versions = parse_versions(r#"{
+            "stable":[], "beta":[], "canary":[], "extended":[],
+            "dev":[
+                {"major":1,"minor":3,"build":0,"patch":0,"count":0,"urls":["https://example.com/artifacts/pkg-1.3.bin"],"halted":true},
+                {"major":1,"minor":2,"build":0,"patch":0,"count":0,"urls":["https://example.com/artifacts/pkg-1.2.bin"],
+                    "deltas":[{"from":"1.1.0.0","url":"https://example.com/artifacts/pkg-1.2.bin.from-1.1.0.0.patch","sha256":"aaaa"}]},
+                {"major":1,"minor":1,"build":0,"patch":0,"count":0,"urls":["https://example.com/artifacts/pkg-1.1.bin"]},
+                {"major":1,"minor":0,"build":0,"patch":0,"count":0,"urls":["https://example.com/artifacts/pkg-1.0.bin"]},
+                {"major":0,"minor":9,"build":0,"patch":0,"count":0,"urls":["https://example.com/pkg-0.9.bin"]},
+                {"major":0,"minor":8,"build":0,"patch":0,"count":0,"urls":["https://example.com/artifacts/pkg-0.8.bin"]}
+            ]
+        }"#).unwrap();
+
+        let jobs = plan(versions.channel(&Channel::Dev), 3, &store);
+        assert_eq!(jobs.len(), 1);
+        let job = &jobs[0];
+        assert_eq!(job.target, "1.2.0.0");
+        assert_eq!(job.delta.from, "1.0.0.0");
+        assert_eq!(job.delta.url, "https://example.com/artifacts/pkg-1.2.bin.from-1.0.0.0.patch");
+        assert_eq!(job.delta.format, DELTA_FORMAT);
+        assert_eq!((job.old_path.clone(), job.new_path.clone()), (store.join("pkg-1.0.bin"), store.join("pkg-1.2.bin")));
+        assert_eq!(job.patch_path, store.join("pkg-1.2.bin.from-1.0.0.0.patch"));
+
+        assert_eq!(plan(versions.channel(&Channel::Dev), 4, &store).len(), 2);
+        assert!(plan(versions.channel(&Channel::Stable), 3, &store).is_empty());
+        let _ = fs::remove_dir_all(&store);
+    }
+
+    #[test]
+    fn patches_per_platform() {
+        let store = store("platforms", &[
+            ("linux/new.tar", b"new"), ("windows/new.exe", b"new"),
+            ("linux/old.tar", b"old"), ("mac/old.dmg", b"old")
+        ]);
+        let build = |platform:&str, arch:&str, file:&str| format!(r#"{{"platform":"{platform}","arch":"{arch}","min_os_version":"1","server":"https://example.com/artifacts/{file}"}}"#);
+        let versions = parse_versions(&format!(r#"{{
+            "stable":[], "beta":[], "dev":[], "extended":[],
+            "canary":[
+                {{"major":2,"minor":0,"build":0,"patch":0,"count":0,"urls":[],"builds":[{}, {}]}},
+                {{"major":1,"minor":0,"build":0,"patch":0,"count":0,"urls":[],"builds":[{}, {}]}}
+            ]
+        }}"#,
+            build("Linux", "x64", "linux/new.tar"), build("Windows", "x86_64", "windows/new.exe"),
+            build("Linux", "x86_64", "linux/old.tar"), build("MacOS", "x86_64", "mac/old.dmg")
+        )).unwrap();
+
+        // Windows has no old package in the store, x64 and x86_64 are the same architecture
+        let jobs = plan(versions.channel(&Channel::Canary), 5, &store);
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].delta.platform, Some(Platform::Linux));
+        assert_eq!(jobs[0].old_path, store.join("linux").join("old.tar"));
+        assert_eq!(jobs[0].delta.url, "https://example.com/artifacts/linux/new.tar.from-1.0.0.0.patch");
+        let _ = fs::remove_dir_all(&store);
+    }
+
+    #[test]
+    fn explicit_pair_options() {
+        let args = |args:&[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>();
+        let options = parse_options(&args(&["--from", "1.0.0", "--to", "linux/new.tar"])).unwrap();
+        assert_eq!(options.pair, Some((String::from("1.0.0"), String::from("linux/new.tar"))));
+        assert_eq!(parse_options(&args(&[])).unwrap().pair, None);
+        assert!(parse_options(&args(&["--from", "1.0.0"])).is_err());
+        assert!(parse_options(&args(&["--to", "1.0.0"])).is_err());
+        assert!(parse_options(&args(&["--from", "1.0.0", "--to", "2.0.0", "--history", "3"])).is_err());
+    }
+
+    #[test]
+    fn patches_an_explicit_pair() {
+        let store = store("pair", &[
+            ("linux/new.tar", b"new"), ("windows/new.exe", b"new"), ("linux/mid.tar", b"mid"),
+            ("linux/old.tar", b"old"), ("windows/old.exe", b"old"), ("linux/beta.tar", b"beta")
+        ]);
+        let build = |platform:&str, file:&str| format!(r#"{{"platform":"{platform}","arch":"x86_64","min_os_version":"1","server":"https://example.com/artifacts/{file}"}}"#);
+        let versions = parse_versions(&format!(r#"{{
+            "stable":[], "dev":[], "extended":[],
+            "beta":[{{"major":1,"minor":5,"build":0,"patch":0,"count":0,"urls":[],"builds":[{}]}}],
+            "canary":[
+                {{"major":3,"minor":0,"build":0,"patch":0,"count":0,"urls":[],"builds":[{}, {}]}},
+                {{"major":2,"minor":0,"build":0,"patch":0,"count":0,"urls":[],"builds":[{}]}},
+                {{"major":1,"minor":0,"build":0,"patch":0,"count":0,"urls":[],"builds":[{}, {}]}}
+            ]
+        }}"#,
+            build("Linux", "linux/beta.tar"),
+            build("Linux", "linux/new.tar"), build("Windows", "windows/new.exe"),
+            build("Linux", "linux/mid.tar"),
+            build("Linux", "linux/old.tar"), build("Windows", "windows/old.exe")
+        )).unwrap();
+        let canary = versions.channel(&Channel::Canary);
+        let pair = |from:&str, to:&str| plan_pair(canary, &versions, from, to, &store)
+            .into_iter()
+            .map(|job| (job.delta.from, job.target, job.old_path, job.new_path))
+            .collect::<Vec<(String, String, PathBuf, PathBuf)>>();
+
+        // version numbers take every platform both have, not only the newest version's history
+        assert_eq!(pair("1.0.0", "3.0.0"), [
+            (String::from("1.0.0.0"), String::from("3.0.0.0"), store.join("linux/old.tar"), store.join("linux/new.tar")),
+            (String::from("1.0.0.0"), String::from("3.0.0.0"), store.join("windows/old.exe"), store.join("windows/new.exe")),
+        ]);
+        // an artifact names a single package, by its path or file name
+        assert_eq!(pair("linux/old.tar", "2.0.0"), [
+            (String::from("1.0.0.0"), String::from("2.0.0.0"), store.join("linux/old.tar"), store.join("linux/mid.tar")),
+        ]);
+        assert_eq!(pair("1.0.0", "new.exe").len(), 1);
+        // the old version can be on another channel
+        assert_eq!(pair("linux/beta.tar", "3.0.0")[0].0, "1.5.0.0");
+        // the two artifacts must be for the same platform, and both must be in the store
+        assert!(pair("windows/old.exe", "linux/new.tar").is_empty());
+        assert!(pair("1.0.0", "4.0.0").is_empty());
+        assert!(pair("3.0.0", "3.0.0").is_empty());
+        let _ = fs::remove_dir_all(&store);
+    }
+
+    #[test]
+    fn patches_are_added_to_the_file_on_disk() {
+        let store = store("record", &[("versions.json", br#"{
+            "stable":[], "beta":[], "canary":[], "extended":[],
+            "dev":[{"major":1,"minor":2,"build":0,"patch":0,"count":0,"urls":[],"rollout":20,
+                "deltas":[{"from":"1.0.0.0","url":"https://example.com/1.2.from-1.0.0.0.patch","sha256":"aaaa"}]}]
+        }"#)]);
+        let path = store.join("versions.json").to_string_lossy().to_string();
+        let job = |from:&str, target:&str| Job{
+            target:String::from(target),
+            delta:Delta{
+                from:String::from(from),
+                url:format!("https://example.com/1.2.from-{from}.patch"),
+                sha256:String::from("bbbb"),
+                size:1,
+                platform:None,
+                arch:None,
+                target_sha256:String::new(),
+                format:String::from(DELTA_FORMAT),
+            },
+            old_path:PathBuf::new(),
+            new_path:PathBuf::new(),
+            patch_path:PathBuf::new(),
+        };
+
+        // the rest of the file is kept, the patch from 1.0.0.0 is in it already and 1.3.0.0 isn't
+        record(&path, vec![(Channel::Dev, job("1.1.0.0", "1.2.0.0")), (Channel::Dev, job("1.0.0.0", "1.2.0.0")), (Channel::Dev, job("1.2.0.0", "1.3.0.0"))]).unwrap();
+        let versions = load_versions(&path).unwrap();
+        let version = &versions.channel(&Channel::Dev)[0];
+        assert_eq!(version.rollout, 20);
+        let recorded = version.deltas.iter().map(|delta| (delta.from.as_str(), delta.sha256.as_str())).collect::<Vec<(&str, &str)>>();
+        assert_eq!(recorded, [("1.0.0.0", "aaaa"), ("1.1.0.0", "bbbb")]);
+        let _ = fs::remove_dir_all(&store);
+    }
+
+    #[test]
+    fn patch_rebuilds_the_new_package() {
+        let old = (0..200_000u32).map(|index| (index.wrapping_mul(2_654_435_761) >> 24) as u8).collect::<Vec<u8>>();
+        let mut new = old.clone();
+        new[1000..1100].fill(7);
+        new.extend_from_slice(b"appended to the new version");
+        let store = store("patch", &[("old.bin", &old), ("new.bin", &new)]);
+
+        let mut job = Job{
+            target:String::from("1.1.0.0"),
+            delta:Delta{
+                from:String::from("1.0.0.0"),
+                url:String::from("https://example.com/artifacts/new.bin.from-1.0.0.0.patch"),
+                sha256:String::new(),
+                size:0,
+                platform:None,
+                arch:None,
+                target_sha256:String::new(),
+                format:String::from(DELTA_FORMAT),
+            },
+            old_path:store.join("old.bin"),
+            new_path:store.join("new.bin"),
+            patch_path:store.join("new.bin.from-1.0.0.0.patch"),
+        };
+        make_patch(&mut job).unwrap();
+
+        let patch = fs::read(&job.patch_path).unwrap();
+        assert_eq!(job.delta.size, patch.len() as u64);
+        assert!(patch.len() < new.len() / 10, "patch of {} bytes", patch.len());
+        assert_eq!(job.delta.sha256, hex::encode(Sha256::digest(&patch)));
+        assert_eq!(job.delta.target_sha256, hex::encode(Sha256::digest(&new)));
+
+        let mut decoder = zstd::stream::read::Decoder::with_ref_prefix(&patch[..], &old).unwrap();
+        decoder.window_log_max(MAX_WINDOW_LOG).unwrap();
+        let mut rebuilt = vec![];
+        decoder.read_to_end(&mut rebuilt).unwrap();
+        assert!(rebuilt == new);
+        let _ = fs::remove_dir_all(&store);
+    }
+}
@@ -0,0 +1,115 @@
+use serde::Serialize;
+use serde_json::Value;
+use crate::http::{create_response_with_type, Response};
+
+// Response encoders for the JSON endpoints. Request.acceptformat picks the encoder by
+// name, clients that leave it empty are matched against their Accept header. New formats
+// only need an encoder added to ENCODERS.
+pub trait ResponseEncoder{
+    fn name(&self) -> &'static str; // value clients send in acceptformat
+    fn content_type(&self) -> &'static str;
+    fn encode(&self, root:&str, value:&Value) -> Result<String, String>;
+}
+
+struct JsonEncoder;
+
+impl ResponseEncoder for JsonEncoder{
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn encode(&self, _root:&str, value:&Value) -> Result<String, String> {
+        serde_json::to_string(value).map_err(|err| err.to_string())
+    }
+}
+
+struct XmlEncoder;
+
+impl ResponseEncoder for XmlEncoder{
+    fn name(&self) -> &'static str {
+        "xml"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/xml"
+    }
+
+    fn encode(&self, root:&str, value:&Value) -> Result<String, String> {
+        let body = quick_xml::se::to_string_with_root(root, value).map_err(|err| err.to_string())?;
+        Ok(format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>{body}"))
+    }
+}
+
+static ENCODERS:&[&(dyn ResponseEncoder + Sync)] = &[&JsonEncoder, &XmlEncoder];
+
+fn find_encoder(name:&str) -> Option<&'static (dyn ResponseEncoder + Sync)> {
+    ENCODERS.iter().copied().find(|encoder| encoder.name().eq_ignore_ascii_case(name.trim()))
+}
+
+// Picks the best encoder for an Accept header, None when nothing the client accepts is supported
+fn from_accept_header(accept:&str) -> Option<&'static (dyn ResponseEncoder + Sync)> {
+    let mut best:Option<(f32, &'static (dyn ResponseEncoder + Sync))> = None;
+    for media_range in accept.split(',') {
+        let mut parts = media_range.split(';');
+        let media_type = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let quality = parts
+            .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+            .find_map(|quality| quality.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if quality <= 0.0 {
+            continue;
+        }
+
+        let encoder = if media_type == "*/*" || media_type == "application/*" {
+            Some(ENCODERS[0])
+        } else {
+            ENCODERS.iter().copied().find(|encoder| encoder.content_type() == media_type || (media_type == "text/xml" && encoder.name() == "xml"))
+        };
+        if let Some(encoder) = encoder {
+            if best.is_none_or(|(best_quality, _)| quality > best_quality) {
+                best = Some((quality, encoder));
+            }
+        }
+    }
+    best.map(|(_, encoder)| encoder)
+}
+
+// The Accept header only picks the format when acceptformat is empty, a response picked that
+// way has to carry Vary: Accept so caches don't hand one client's format to another
+pub fn negotiated_by_accept(acceptformat:&str) -> bool {
+    acceptformat.trim().is_empty()
+}
+
+// Name of the encoder to answer with, None means the request has to be rejected with a 406
+pub fn negotiate(acceptformat:&str, accept:Option<&str>) -> Option<&'static str> {
+    if !negotiated_by_accept(acceptformat) {
+        return find_encoder(acceptformat).map(|encoder| encoder.name());
+    }
+    match accept {
+        Some(accept) if !accept.trim().is_empty() => from_accept_header(accept).map(|encoder| encoder.name()),
+        _ => Some(ENCODERS[0].name())
+    }
+}
+
+pub fn supported_formats() -> String {
+    ENCODERS.iter().map(|encoder| encoder.content_type()).collect::<Vec<&str>>().join(", ")
+}
+
+// Builds the HTTP response with the encoder negotiated for the request, root names the XML root element
+pub fn create_encoded_response<T: Serialize>(status_code:i32, format:&str, root:&str, response_object:&T) -> Response {
+    let encoder = find_encoder(format).unwrap_or(ENCODERS[0]);
+    let encoded = serde_json::to_value(response_object)
+        .map_err(|err| err.to_string())
+        .and_then(|value| encoder.encode(root, &value));
+    match encoded {
+        Ok(body) => create_response_with_type(status_code, encoder.content_type(), &body),
+        Err(err) => {
+            println!("Failed to encode {} response: {}", encoder.name(), err);
+            create_response_with_type(500, "text/plain", "failed to encode response")
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use serde::{de::DeserializeOwned, Serialize};
+use crate::{artifacts, admin, cup, encoding, latest, omaha, omaha_json, protocol, query, signing};
+use crate::{default_request, default_version, generate_id, Action, AppResponse, DownloadResponse, EventType, LatestResponse, Request, Status, StatusRequest, StatusResponse};
+use crate::session::{get_session, mark_delta_failed, new_session, remove_session, update_current_action, update_request, update_session_actions, update_session_mirrors, Session, SessionManager};
+use crate::version::Version;
+use crate::latest::Offer;
+use crate::encoding::create_encoded_response;
+use crate::catalog::Catalog;
+use crate::mirror::MirrorState;
+use crate::router::{Handler, Router};
+use crate::server::ServerState;
+use crate::http::{add_vary, create_response, create_response_with_type, with_header, HttpRequest, Response};
+
+// The endpoints of the server and the update protocol behind /latest, /download and /status.
+// Every endpoint takes the parsed request and the server state and returns its response.
+
+// Lets the update endpoints reach the Request inside the body they read
+impl AsMut<Request> for Request{
+    fn as_mut(&mut self) -> &mut Request {
+        self
+    }
+}
+
+impl AsMut<Request> for StatusRequest{
+    fn as_mut(&mut self) -> &mut Request {
+        &mut self.request
+    }
+}
+
+// Stores the negotiated response format in acceptformat, Err is the 406 for a client that accepts nothing we can encode
+fn negotiate_format(request:&HttpRequest, update_request:&mut Request) -> Result<(), Response> {
+    match encoding::negotiate(&update_request.acceptformat, request.header("Accept").as_deref()) {
+        Some(format) => {
+            update_request.acceptformat = String::from(format);
+            Ok(())
+        },
+        None => {
+            let message = format!("Not Acceptable, supported formats: {}", encoding::supported_formats());
+            Err(create_response_with_type(406, "text/plain", &message))
+        }
+    }
+}
+
+// Reads the update request and answers it with the endpoint. A response whose format came
+// from the Accept header, the 406 included, carries Vary: Accept.
+fn answer_request<T: Serialize + DeserializeOwned + AsMut<Request>>(request:&HttpRequest, default:T, fallback:Option<&str>, endpoint:impl FnOnce(T) -> Response) -> Response {
+    let (mut response, by_accept) = match read_request(request, default, fallback) {
+        Ok((update_request, by_accept)) => (endpoint(update_request), by_accept),
+        Err((response, by_accept)) => (response, by_accept)
+    };
+    if by_accept {
+        add_vary(&mut response.headers, "Accept");
+    }
+    response
+}
+
+// Reads an update request from a JSON body (POST, or GET as older clients send it) or from the
+// query of a GET, and negotiates the format of the answer. Err is the 400 or 406 to send back,
+// both come with whether the Accept header picked the format.
+fn read_request<T: Serialize + DeserializeOwned + AsMut<Request>>(request:&HttpRequest, default:T, fallback:Option<&str>) -> Result<(T, bool), (Response, bool)> {
+    let update_request = if !request.body.trim().is_empty() {
+        serde_json::from_str::<T>(&request.body).map_err(|err| format!("invalid request body: {err}"))
+    } else if request.method == "GET" && !request.query.is_empty() {
+        serde_json::to_value(&default)
+            .map_err(|err| err.to_string())
+            .and_then(|mut value| query::apply_query(&mut value, &request.query, fallback).map(|_| value))
+            .and_then(|value| serde_json::from_value::<T>(value).map_err(|err| err.to_string()))
+            .map_err(|err| format!("invalid query: {err}"))
+    } else {
+        Ok(default)
+    };
+    let mut update_request = update_request.map_err(|message| {
+        println!("Rejected request: {}", message);
+        (create_response_with_type(400, "text/plain", &message), false)
+    })?;
+    let by_accept = encoding::negotiated_by_accept(&update_request.as_mut().acceptformat);
+    negotiate_format(request, update_request.as_mut()).map_err(|response| (response, by_accept))?;
+    Ok((update_request, by_accept))
+}
+
+fn unknown_product(request:&Request) -> String {
+    format!("unknown product {}", request.updater)
+}
+
+// HTTP status code of an update response, the same for /latest, /download and /status
+fn http_status(status:&Status) -> i32 {
+    match status {
+        Status::ok | Status::noupdate | Status::updatecomplete | Status::updateabandoned => 200,
+        Status::errorinternal => 500,
+        Status::errorosnotsupported => 406,
+        Status::errorhwnotsupported => 428,
+        Status::errorunsupportedprotocol => 401,
+        Status::errorunknownproduct | Status::errorinvalidsession => 404,
+        _ => 400
+    }
+}
+
+// What a client may do next after an update check, the last version supporting an old OS
+// can still be downloaded
+fn latest_actions(offer:&Offer) -> Vec<Action> {
+    match offer.status {
+        Status::ok | Status::errorhwnotsupported => vec![Action::download, Action::abandon],
+        Status::errorosnotsupported if !offer.downloadlink.is_empty() => vec![Action::download, Action::abandon],
+        Status::noupdate | Status::errorunknownproduct => vec![],
+        Status::errorinternal => vec![Action::retry, Action::abandon],
+        _ => vec![Action::abandon]
+    }
+}
+
+// Every update response echoes the ids, a request without them can't be answered
+fn missing_ids(request:&Request) -> Option<Response> {
+    if request.requestid.is_empty() || request.sessionid.is_empty() {
+        return Some(create_response(500, &serde_json::to_string("").unwrap()));
+    }
+    None
+}
+
+// A rejected request starts no session, generated ids only let the client match the answer
+fn fill_missing_ids(request:&mut Request) {
+    if request.sessionid.is_empty() {
+        request.sessionid = generate_id();
+    }
+    if request.requestid.is_empty() {
+        request.requestid = generate_id();
+    }
+}
+
+fn latest_response(offer:&Offer, request: &Request, apps:Vec<AppResponse>) -> Response {
+    if let Some(response) = missing_ids(request) {
+        return response;
+    }
+    let response_object  = LatestResponse {
+        actions: latest_actions(offer),
+        info:offer.info.clone(),
+        status:offer.status.clone(),
+        version: offer.version.to_string(),
+        sessionid:request.sessionid.to_string(),
+        requestid:request.requestid.to_string(),
+        downgrade:offer.downgrade,
+        protocol:protocol::negotiated(request.protocol),
+        sha256:offer.sha256.clone(),
+        size:offer.size,
+        apps,
+        delta:offer.delta.clone()
+    };
+    create_encoded_response(http_status(&offer.status), &request.acceptformat, "latest", &response_object)
+}
+
+fn download_response(offer:&Offer, request: &Request, session_manager:&SessionManager, mirrors:Vec<String>) -> Response {
+    if let Some(response) = missing_ids(request) {
+        return response;
+    }
+    let actions = if offer.status == Status::errorunknownproduct {
+        vec![]
+    } else {
+        get_session(session_manager, request).map(|session| session.possible_actions.clone()).unwrap_or_default()
+    };
+    let response_object = DownloadResponse{
+        actions,
+        info:offer.info.clone(),
+        status:offer.status.clone(),
+        sessionid:request.sessionid.to_string(),
+        requestid:request.requestid.to_string(),
+        downloadlink : mirrors.first().cloned().unwrap_or_else(|| offer.downloadlink.clone()),
+        downgrade:offer.downgrade,
+        protocol:protocol::negotiated(request.protocol),
+        sha256:offer.sha256.clone(),
+        size:offer.size,
+        mirrors,
+        delta:offer.delta.clone()
+    };
+
+    if offer.status == Status::ok && request.redirect && !response_object.downloadlink.is_empty() {
+        let response = create_encoded_response(302, &request.acceptformat, "download", &response_object);
+        return with_header(response, "Location", &response_object.downloadlink);
+    }
+    create_encoded_response(http_status(&offer.status), &request.acceptformat, "download", &response_object)
+}
+
+fn status_response(status:Status, request: &Request) -> Response {
+    if let Some(response) = missing_ids(request) {
+        return response;
+    }
+    let response_object = StatusResponse{
+        sessionid:request.sessionid.clone(),
+        requestid:request.requestid.clone(),
+        status,
+        protocol:protocol::negotiated(request.protocol)
+    };
+    create_encoded_response(http_status(&response_object.status), &request.acceptformat, "status", &response_object)
+}
+
+// Update responses carry the signature of their body, see signing.rs
+fn signed(endpoint:fn(&HttpRequest, &mut ServerState) -> Response) -> impl Handler {
+    move |request:&HttpRequest, state:&mut ServerState| {
+        let response = endpoint(request, state);
+        signing::sign(response, state.signing_key.as_ref())
+    }
+}
+
+// All endpoints of the server, new ones only need a line here
+pub fn routes(pages:HashMap<String, String>, artifacts_dir:PathBuf) -> Router {
+    Router::new()
+        .route(&["GET", "POST"], "/latest", signed(latest_endpoint))
+        .route(&["GET", "POST"], "/download", signed(download_endpoint))
+        .route(&["GET", "POST"], "/status", signed(status_endpoint))
+        .files(&["GET", "HEAD"], artifacts::ARTIFACTS_ROUTE, artifact_endpoint(artifacts_dir))
+        .route(&["GET"], "/publickey", public_key_endpoint)
+        .route(&["GET"], "/cup/publickey", cup_public_key_endpoint)
+        .route(&["POST"], "/service/update2", update2_endpoint)
+        .route(&["POST"], "/service/update2/json", update2_json_endpoint)
+        .route(&["POST"], "/admin/rollout", rollout_endpoint)
+        .route(&["POST"], "/admin/halt", halt_endpoint)
+        .route(&["POST"], "/admin/rollback", rollback_endpoint)
+        .route(&["POST"], "/admin/reload", reload_endpoint)
+        .pages(pages)
+}
+
+// GET|POST /latest, equivalent of update-check
+pub fn latest_endpoint(request:&HttpRequest, state:&mut ServerState) -> Response {
+    answer_request(request, default_request(), None, |mut request_data| {
+        let default_version = default_version();
+        let Some(catalog) = state.products.get(&request_data.updater) else {
+            println!("Rejected update check: {}", unknown_product(&request_data));
+            fill_missing_ids(&mut request_data);
+            return latest_response(&Offer::none(&default_version, Status::errorunknownproduct, &unknown_product(&request_data)), &request_data, vec![]);
+        };
+        handle_latest(&default_version, catalog, &mut state.session_manager, &mut request_data)
+    })
+}
+
+// GET|POST /download, the download phase/ping check
+pub fn download_endpoint(request:&HttpRequest, state:&mut ServerState) -> Response {
+    answer_request(request, default_request(), None, |mut request_data| {
+        let default_version = default_version();
+        let Some(catalog) = state.products.get(&request_data.updater) else {
+            println!("Rejected download request: {}", unknown_product(&request_data));
+            fill_missing_ids(&mut request_data);
+            return download_response(&Offer::none(&default_version, Status::errorunknownproduct, &unknown_product(&request_data)), &request_data, &state.session_manager, vec![]);
+        };
+        handle_download(&default_version, catalog, &mut state.session_manager, &mut state.mirror_state, &mut request_data, false)
+    })
+}
+
+// GET|POST /status, equivalent of ping-back
+pub fn status_endpoint(request:&HttpRequest, state:&mut ServerState) -> Response {
+    let default_status_request = StatusRequest{
+        eventtype:EventType::None,
+        result:1,
+        action:Action::retry,
+        request:default_request(),
+        mirror:String::from(""),
+        delta:false
+    };
+    answer_request(request, default_status_request, Some("request"), |mut request_data| {
+        if let Err(info) = protocol::negotiate(request_data.request.protocol) {
+            println!("Rejected status request: {}", info);
+            fill_missing_ids(&mut request_data.request);
+            return status_response(Status::errorunsupportedprotocol, &request_data.request);
+        }
+        let Some(catalog) = state.products.get(&request_data.request.updater) else {
+            println!("Rejected status request: {}", unknown_product(&request_data.request));
+            fill_missing_ids(&mut request_data.request);
+            return status_response(Status::errorunknownproduct, &request_data.request);
+        };
+
+        handle_status(&default_version(), catalog, &mut state.session_manager, &mut state.mirror_state, &mut request_data)
+    })
+}
+
+// GET|HEAD /artifacts/<path>, package files, resumable. Served from the store alone, without the state
+pub fn artifact_endpoint(artifacts_dir:PathBuf) -> impl Fn(&HttpRequest) -> Response + Send + Sync {
+    move |request:&HttpRequest| artifacts::handle_artifact(request, &artifacts_dir)
+}
+
+// GET /publickey, key that signs the update responses
+pub fn public_key_endpoint(_request:&HttpRequest, state:&mut ServerState) -> Response {
+    signing::handle_public_key(state.signing_key.as_ref())
+}
+
+// GET /cup/publickey, key that signs the CUP proofs of the Omaha endpoints
+pub fn cup_public_key_endpoint(_request:&HttpRequest, state:&mut ServerState) -> Response {
+    cup::handle_public_key(state.cup_key.as_ref())
+}
+
+// POST /service/update2, Omaha v3 XML clients, e.g the stock Chromium updater
+pub fn update2_endpoint(request:&HttpRequest, state:&mut ServerState) -> Response {
+    omaha::handle_update2(request, &default_version(), &state.products, &mut state.session_manager, &mut state.mirror_state, state.cup_key.as_ref(), &mut state.nonces)
+}
+
+// POST /service/update2/json, Omaha 4 JSON clients
+pub fn update2_json_endpoint(request:&HttpRequest, state:&mut ServerState) -> Response {
+    omaha_json::handle_update2_json(request, &default_version(), &state.products, &mut state.session_manager, &mut state.mirror_state, state.cup_key.as_ref(), &mut state.nonces)
+}
+
+pub fn rollout_endpoint(request:&HttpRequest, state:&mut ServerState) -> Response {
+    admin::handle_rollout(request, &mut state.products, state.admin_token.as_deref())
+}
+
+pub fn halt_endpoint(request:&HttpRequest, state:&mut ServerState) -> Response {
+    admin::handle_halt(request, &mut state.products, state.admin_token.as_deref())
+}
+
+pub fn rollback_endpoint(request:&HttpRequest, state:&mut ServerState) -> Response {
+    admin::handle_rollback(request, &mut state.products, state.admin_token.as_deref())
+}
+
+pub fn reload_endpoint(request:&HttpRequest, state:&mut ServerState) -> Response {
+    admin::handle_reload(request, &mut state.products, state.admin_token.as_deref())
+}
+
+
+fn handle_latest(default_version:&Version, catalog:&Catalog, session_manager:&mut SessionManager, request_data:&mut Request) -> Response {
+    if request_data.sessionid.is_empty() {
+        request_data.sessionid = generate_id();
+    }
+
+    // checked before the session is touched, a rejected client has nothing to continue
+    if let Err(info) = protocol::negotiate(request_data.protocol) {
+        println!("Rejected update check: {}", info);
+        fill_missing_ids(request_data);
+        return latest_response(&Offer::none(default_version, Status::errorunsupportedprotocol, &info), request_data, vec![]);
+    }
+
+    if !new_session(session_manager, request_data) {
+        println!("Failed to create a new session because session already exists");
+        return latest_response(&Offer::none(default_version, Status::errorinternal, "session already exists"), request_data, vec![]);
+    }
+
+    if request_data.requestid.is_empty() {
+        let new_request_id = generate_id();
+        request_data.requestid = new_request_id.clone();
+        update_request(session_manager, request_data, new_request_id);
+        update_current_action(session_manager, request_data, Action::latest);
+        update_session_actions(session_manager, request_data, vec![Action::latest,Action::download, Action::abandon, Action::retry]);
+    }
+
+    let offer = latest::select_version(&catalog.main.versions, request_data, default_version);
+    if offer.status != Status::ok {
+        println!("No update on channel {} for this client: {}", request_data.channel, offer.info);
+    }
+    let apps = latest::check_apps(catalog, request_data, default_version);
+    latest_response(&offer, request_data, apps)
+}
+
+fn handle_download(default_version:&Version, catalog:&Catalog, session_manager:&mut SessionManager, mirror_state:&mut MirrorState, request_data:&mut Request, ping_back:bool) -> Response {
+    if let Err(info) = protocol::negotiate(request_data.protocol) {
+        println!("Rejected download: {}", info);
+        return download_response(&Offer::none(default_version, Status::errorunsupportedprotocol, &info), request_data, session_manager, vec![]);
+    }
+
+    if let Some(current_session) = get_session(session_manager, request_data) {
+        let new_download = current_session.requestid == request_data.requestid && current_session.previous_action == Action::latest && current_session.possible_actions.contains(&Action::download) ;
+        if new_download || ping_back {
+            let new_request_id = generate_id();
+            let previous_action = Action::download;
+            let update_request_result = update_request(session_manager, request_data, new_request_id.clone());
+            if update_request_result.0 {
+                request_data.requestid = new_request_id;
+                let update_current_action = update_current_action(session_manager, request_data, previous_action);
+                if update_current_action.0 {
+                    let update_session_actions = update_session_actions(session_manager, request_data, vec![Action::abandon, Action::retry]);
+                    if update_session_actions.0 {
+                        // all data is updated, create and send response
+                        let mut offer = latest::select_version(&catalog.main.versions, request_data, default_version);
+                        if offer.status != Status::ok {
+                            println!("No update on channel {} for this client: {}", request_data.channel, offer.info);
+                        }
+                        if get_session(session_manager, request_data).is_some_and(|session| session.delta_failed) {
+                            offer.delta = None;
+                        }
+                        let mirrors = mirror_state.order(&offer.mirrors, &request_data.region);
+                        update_session_mirrors(session_manager, request_data, mirrors.clone());
+                        return download_response(&offer, request_data, session_manager, mirrors);
+                    }
+                }
+            }
+        }
+    }
+    download_response(&Offer::none(default_version, Status::noupdate, "no download available for this session"), request_data, session_manager, vec![])
+}
+
+fn handle_status_action(default_version:&Version, catalog:&Catalog, session_manager:&mut SessionManager, mirror_state:&mut MirrorState, request_data:&mut Request, action:&Action, previous_action:&Action) -> Response {
+    match action {
+        Action::retry => {
+            // we could store the last response and send it again??
+            match previous_action {
+                Action::latest => handle_latest(default_version, catalog, session_manager, request_data),
+                Action::download => handle_download(default_version, catalog, session_manager, mirror_state, request_data, true),
+                _ => status_response(Status::errorunsupportedprotocol, request_data)
+            }
+        },
+        Action::abandon => {
+            remove_session(session_manager, request_data);   // we delete your session and send back a success response
+            status_response(Status::updateabandoned, request_data)
+        },
+        Action::complete => {
+            remove_session(session_manager, request_data);   // clear session and send back response
+            status_response(Status::updatecomplete, request_data)
+        }
+        _ => status_response(Status::errorunsupportedprotocol, request_data)
+    }
+}
+
+// Only a mirror the session was offered can be reported, so no client can demote other
+// mirrors or grow the health table with urls of its own
+fn report_mirror(mirror_state:&mut MirrorState, session:&Session, request_data:&StatusRequest) {
+    if request_data.mirror.is_empty() || !session.mirrors.contains(&request_data.mirror) {
+        return;
+    }
+    match request_data.result {
+        0 => mirror_state.report_failure(&request_data.mirror),
+        1 => mirror_state.report_success(&request_data.mirror),
+        _ => {}
+    }
+}
+
+pub fn handle_status(default_version:&Version, catalog:&Catalog, session_manager:&mut SessionManager, mirror_state:&mut MirrorState, request_data:&mut StatusRequest) -> Response {
+    if let Some(current_session) = get_session(session_manager, &request_data.request).cloned() {
+        if current_session.possible_actions.contains(&request_data.action) {
+            report_mirror(mirror_state, &current_session, request_data);
+            if request_data.delta && request_data.result == 0 && mark_delta_failed(session_manager, &request_data.request) {
+                println!("Delta patch failed for session {}, falling back to the full package", request_data.request.sessionid);
+            }
+            return match request_data.result{
+                0 | 2 => handle_status_action(default_version, catalog, session_manager, mirror_state, &mut request_data.request, &request_data.action, &current_session.previous_action),
+                1 => {
+                    remove_session(session_manager, &request_data.request);
+                    status_response(Status::ok, &request_data.request)
+                }
+                _ => status_response(Status::errorinternal, &request_data.request)
+            };
+        }
+    }
+    println!("Rejected status request: session {} doesn't exist or doesn't allow this action", request_data.request.sessionid);
+    status_response(Status::errorinvalidsession, &request_data.request)
+}
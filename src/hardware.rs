@@ -0,0 +1,86 @@
+use serde::{Serialize, Deserialize};
+use crate::Hardware;
+
+// Minimum hardware a catalog entry needs to run. Clients report unknown values as -1,
+// those are treated as supported so older updaters keep getting updates.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct HardwareRequirements{
+    #[serde(default)]
+    pub instructions:Vec<String>, // required instruction sets, named like the Hardware fields: "sse42", "avx"
+    #[serde(default)]
+    pub physmemory:i32, // minimum physical memory in GB, 0 => no requirement
+}
+
+fn instruction_support(hw:&Hardware, instruction:&str) -> Option<i32> {
+    match instruction {
+        "sse" => Some(hw.sse),
+        "sse2" => Some(hw.sse2),
+        "sse3" => Some(hw.sse3),
+        "sse41" => Some(hw.sse41),
+        "sse42" => Some(hw.sse42),
+        "avx" => Some(hw.avx),
+        _ => None
+    }
+}
+
+// Returns what the client is missing to run a version
+pub fn check(hw:&Hardware, requirements:&HardwareRequirements) -> Result<(), String> {
+    let mut missing = vec![];
+    for instruction in &requirements.instructions {
+        match instruction_support(hw, instruction) {
+            Some(0) => missing.push(instruction.clone()),
+            Some(_) => {},
+            None => {
+                println!("Unknown instruction set {} in catalog requirements", instruction);
+                missing.push(instruction.clone());
+            }
+        }
+    }
+
+    if requirements.physmemory > 0 && hw.physmemory >= 0 && hw.physmemory < requirements.physmemory {
+        missing.push(format!("{}GB of physical memory", requirements.physmemory));
+    }
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(missing.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::default_request;
+
+    fn requirements(instructions:&[&str], physmemory:i32) -> HardwareRequirements {
+        HardwareRequirements{ instructions:instructions.iter().map(|instruction| instruction.to_string()).collect(), physmemory }
+    }
+
+    #[test]
+    fn missing_instruction_set() {
+        let mut hw = default_request().hw;
+        (hw.sse42, hw.avx) = (1, 0);
+        assert!(check(&hw, &requirements(&["sse42"], 0)).is_ok());
+        assert_eq!(check(&hw, &requirements(&["sse42", "avx"], 0)), Err(String::from("avx")));
+        // a name the server doesn't know can't be checked, so it isn't assumed to be there
+        assert_eq!(check(&hw, &requirements(&["avx512"], 0)), Err(String::from("avx512")));
+    }
+
+    #[test]
+    fn too_little_memory() {
+        let mut hw = default_request().hw;
+        hw.physmemory = 4;
+        assert!(check(&hw, &requirements(&[], 4)).is_ok());
+        assert_eq!(check(&hw, &requirements(&[], 8)), Err(String::from("8GB of physical memory")));
+        hw.avx = 0;
+        assert_eq!(check(&hw, &requirements(&["avx"], 8)), Err(String::from("avx, 8GB of physical memory")));
+    }
+
+    // older updaters report -1 for everything, they keep getting updates
+    #[test]
+    fn unknown_values_are_supported() {
+        let hw = default_request().hw;
+        assert!(check(&hw, &requirements(&["sse", "sse2", "sse3", "sse41", "sse42", "avx"], 16)).is_ok());
+    }
+}
@@ -0,0 +1,331 @@
+use std::fs::File;
+use std::io::{self, prelude::*, SeekFrom};
+
+// Requests and responses as the handlers see them. The connection reads an HttpRequest,
+// the router hands it to a handler and the handler's Response is written back, so handlers
+// never touch the socket and can be called on their own.
+pub struct HttpRequest{
+    pub method:String,
+    pub path:String, // without the query
+    pub query:String, // everything after '?', not decoded
+    pub headers:String, // raw header block, one "Name: value" per line
+    pub body:String,
+}
+
+impl HttpRequest{
+    // None when the request line isn't "<method> <target> <version>"
+    pub fn new(request_line:&str, headers:String, body:String) -> Option<HttpRequest> {
+        let parts = request_line.split_whitespace().collect::<Vec<&str>>();
+        let [method, target, _version] = parts[..] else {
+            return None;
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        Some(HttpRequest{ method:String::from(method), path:String::from(path), query:String::from(query), headers, body })
+    }
+
+    pub fn header(&self, name:&str) -> Option<String> {
+        header_value(&self.headers, name)
+    }
+}
+
+// Bodies above this are refused with a 413 instead of being read into memory
+pub const MAX_BODY_SIZE:u64 = 1024 * 1024;
+
+// Anything but a single Content-Length leaves the body unread and ends the connection, a
+// proxy in front could take a different length and read the rest as a request of its own
+pub enum RequestBody{
+    Complete(String),
+    Truncated, // the stream ended before Content-Length bytes arrived
+    TimedOut, // the client didn't send Content-Length bytes in time
+    TooLarge(u64), // Content-Length is above MAX_BODY_SIZE, the body is left unread
+    InvalidLength, // Content-Length isn't a number or is sent more than once
+    TransferEncoded, // chunked and other transfer codings aren't supported
+}
+
+// The request line and headers together, a longer head or more headers get a 431 and the
+// connection is closed, so a client can't grow the head without bound
+pub const MAX_HEAD_SIZE:usize = 64 * 1024;
+pub const MAX_HEADERS:usize = 100;
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum Head{
+    Complete, // ended with an empty line
+    Truncated, // the stream ended or failed first
+    TimedOut, // the client stopped sending before the end
+    TooLarge, // over MAX_HEAD_SIZE or MAX_HEADERS, the rest of the request is left unread
+}
+
+// A request as it comes off the connection, before the request line is parsed
+pub struct RawRequest{
+    pub request_line:String,
+    pub headers:String,
+    pub head:Head,
+    pub body:RequestBody, // Truncated unless the head is Complete
+}
+
+// A line of the request head, at most limit bytes of it. None at the end of the stream.
+fn read_line(reader:&mut impl BufRead, limit:usize) -> io::Result<Option<Vec<u8>>> {
+    let mut line = vec![];
+    if reader.by_ref().take(limit as u64).read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+fn timed_out(err:&io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+// Reads the next request of a connection. None when the client closed it, Err when the
+// request line can't be read, e.g an idle client timing out. A request line cut off by a
+// timeout is a TimedOut head. Bytes that aren't UTF-8 are replaced rather than failing the read.
+pub fn read_request(reader:&mut impl BufRead) -> io::Result<Option<RawRequest>> {
+    let mut line = vec![];
+    match reader.by_ref().take(MAX_HEAD_SIZE as u64).read_until(b'\n', &mut line) {
+        Ok(0) => return Ok(None),
+        Ok(_) => {},
+        Err(err) if timed_out(&err) && !line.is_empty() => {
+            let request_line = String::from_utf8_lossy(&line).into_owned();
+            return Ok(Some(RawRequest{ request_line, headers:String::new(), head:Head::TimedOut, body:RequestBody::Truncated }));
+        },
+        Err(err) => return Err(err)
+    }
+    let request_line = String::from_utf8_lossy(&line).into_owned();
+
+    let mut size = line.len();
+    let mut headers = String::new();
+    let mut header_count = 0;
+    let head = loop {
+        if size >= MAX_HEAD_SIZE || header_count > MAX_HEADERS {
+            break Head::TooLarge;
+        }
+        let line = match read_line(reader, MAX_HEAD_SIZE - size) {
+            Ok(Some(line)) => line,
+            Err(err) if timed_out(&err) => break Head::TimedOut,
+            _ => break Head::Truncated
+        };
+        size += line.len();
+        if line == b"\r\n" {
+            break Head::Complete;
+        }
+        headers.push_str(&String::from_utf8_lossy(&line));
+        header_count += 1;
+    };
+
+    // without the end of the head there's no telling where a body would start
+    let body = if head == Head::Complete { read_body(reader, &headers) } else { RequestBody::Truncated };
+    Ok(Some(RawRequest{ request_line, headers, head, body }))
+}
+
+fn read_body(reader:&mut impl BufRead, headers:&str) -> RequestBody {
+    if !header_values(headers, "Transfer-Encoding").is_empty() {
+        return RequestBody::TransferEncoded;
+    }
+    let content_length = match &header_values(headers, "Content-Length")[..] {
+        [] => 0,
+        // digits only, parse would also take a leading +
+        [length] if !length.is_empty() && length.bytes().all(|byte| byte.is_ascii_digit()) => match length.parse::<u64>() {
+            Ok(length) => length,
+            Err(_) => return RequestBody::InvalidLength
+        },
+        _ => return RequestBody::InvalidLength
+    };
+    if content_length > MAX_BODY_SIZE {
+        return RequestBody::TooLarge(content_length);
+    }
+    let mut buffer = vec![];
+    match reader.take(content_length).read_to_end(&mut buffer) {
+        Ok(read) if read as u64 == content_length => RequestBody::Complete(String::from_utf8_lossy(&buffer).into_owned()),
+        Err(err) if timed_out(&err) => RequestBody::TimedOut,
+        _ => RequestBody::Truncated
+    }
+}
+
+pub enum Body{
+    Bytes(Vec<u8>),
+    File{ file:File, start:u64, length:u64 }, // streamed from disk, e.g a package
+}
+
+pub struct Response{
+    pub status_code:i32,
+    pub headers:Vec<(String, String)>, // Content-Length is added when the response is written
+    pub body:Body,
+}
+
+impl Response{
+    pub fn header(&self, name:&str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    // Body of an in-memory response, empty for files
+    pub fn bytes(&self) -> &[u8] {
+        match &self.body {
+            Body::Bytes(bytes) => bytes,
+            Body::File{..} => &[]
+        }
+    }
+
+    fn content_length(&self) -> u64 {
+        match &self.body {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File{ length, .. } => *length
+        }
+    }
+}
+
+// Case-insensitive lookup of a header in the raw header block
+pub fn header_value(headers:&str, name:&str) -> Option<String> {
+    header_values(headers, name).into_iter().next()
+}
+
+// Every value of a header that may be sent more than once, in the order sent
+pub fn header_values(headers:&str, name:&str) -> Vec<String> {
+    headers.lines().filter_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        if key.trim().eq_ignore_ascii_case(name) { Some(value.trim().to_string()) } else { None }
+    }).collect()
+}
+
+// Adds a header to a response built by create_response
+pub fn with_header(mut response:Response, name:&str, value:&str) -> Response {
+    response.headers.insert(0, (String::from(name), String::from(value)));
+    response
+}
+
+// Vary lists every request header the response depends on, in a single header
+pub fn add_vary(headers:&mut Vec<(String, String)>, name:&str) {
+    match headers.iter_mut().find(|(key, _)| key.eq_ignore_ascii_case("Vary")) {
+        Some((_, value)) if value.split(',').any(|item| item.trim().eq_ignore_ascii_case(name)) => {},
+        Some((_, value)) => *value = format!("{value}, {name}"),
+        None => headers.push((String::from("Vary"), String::from(name)))
+    }
+}
+
+pub fn create_response(status_code:i32, message:&str) -> Response {
+    create_response_with_type(status_code, "application/json", message)
+}
+
+pub fn create_response_with_type(status_code:i32, content_type:&str, message:&str) -> Response {
+    Response{
+        status_code,
+        headers:vec![(String::from("Content-Type"), String::from(content_type))],
+        body:Body::Bytes(message.as_bytes().to_vec())
+    }
+}
+
+pub fn status_text(status_code:i32) -> &'static str {
+    match status_code {
+        // Informational responses (100–199)
+        100 => "Continue",
+        101 => "Switching Protocols",
+        102 => "Processing",
+        103 => "Early Hints",
+
+        // Successful responses (200–299)
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        203 => "Non-Authoritative Information",
+        204 => "No Content",
+        205 => "Reset Content",
+        206 => "Partial Content",
+        207 => "Multi-Status",
+        208 => "Already Reported",
+        226 => "IM Used",
+
+        // Redirection messages (300–399)
+        300 => "Multiple Choices",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        305 => "Use Proxy",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+
+        // Client error responses (400–499)
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        402 => "Payment Required",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        407 => "Proxy Authentication Required",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        418 => "I'm a teapot",
+        421 => "Misdirected Request",
+        422 => "Unprocessable Entity",
+        423 => "Locked",
+        424 => "Failed Dependency",
+        425 => "Too Early",
+        426 => "Upgrade Required",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        451 => "Unavailable For Legal Reasons",
+
+        // Server error responses (500–599)
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        506 => "Variant Also Negotiates",
+        507 => "Insufficient Storage",
+        508 => "Loop Detected",
+        510 => "Not Extended",
+        511 => "Network Authentication Required",
+        _ => "Unknown Status Code",
+    }
+}
+
+// Status line and headers, with the Content-Length of the body unless the status has none
+pub fn response_head(response:&Response) -> String {
+    let status_code = response.status_code;
+    let mut head = format!("HTTP/1.1 {status_code} {}\r\n", status_text(status_code));
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if status_code != 304 && status_code != 204 && response.header("Content-Length").is_none() {
+        head.push_str(&format!("Content-Length: {}\r\n", response.content_length()));
+    }
+    head.push_str("\r\n");
+    head
+}
+
+// HEAD requests get the head of the GET response only
+pub fn write_response<W: Write>(mut stream:W, response:Response, head_only:bool) -> io::Result<()> {
+    let head = response_head(&response);
+    match response.body {
+        Body::Bytes(bytes) => {
+            let mut buffer = head.into_bytes();
+            if !head_only {
+                buffer.extend_from_slice(&bytes);
+            }
+            stream.write_all(&buffer)
+        },
+        Body::File{ mut file, start, length } => {
+            stream.write_all(head.as_bytes())?;
+            if head_only {
+                return Ok(());
+            }
+            file.seek(SeekFrom::Start(start))?;
+            let copied = io::copy(&mut (&mut file).take(length), &mut stream)?;
+            if copied != length {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while sending"));
+            }
+            Ok(())
+        }
+    }
+}
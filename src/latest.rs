@@ -0,0 +1,490 @@
+use crate::{delta, hardware, os, rollout, AppResponse, Request, Status, SysRequirements};
+use crate::catalog::Catalog;
+use crate::mirror::Mirror;
+use crate::delta::Delta;
+use crate::version::{parse_number, Version, Versions};
+
+pub struct Offer<'a>{
+    pub status:Status,
+    pub version:&'a Version,
+    pub downloadlink:String, // package matching the client's platform
+    pub mirrors:Vec<Mirror>, // every place the package can be downloaded from, downloadlink is the first
+    pub sha256:String, // hex digest of the package, empty when the catalog has none
+    pub size:u64,
+    pub downgrade:bool, // the client is on a halted version and is sent back to this one
+    pub delta:Option<Delta>, // patch from the client's version to this one
+    pub info:String, // explanation sent to the client
+}
+
+impl<'a> Offer<'a>{
+    fn new(status:Status, version:&'a Version, build:Option<&SysRequirements>, info:String) -> Offer<'a> {
+        let (mirrors, sha256, size) = match build {
+            Some(build) if !build.server.is_empty() => (vec![Mirror::new(&build.server)], build.sha256.clone(), build.size),
+            _ => (version.mirror_set(), version.sha256.clone(), version.size)
+        };
+        let downloadlink = mirrors.first().map(|mirror| mirror.url.clone()).unwrap_or_default();
+        Offer{ status, version, downloadlink, mirrors, sha256, size, downgrade:false, delta:None, info }
+    }
+
+    // Nothing to download, default_version only fills the version field of the response
+    pub fn none(default_version:&'a Version, status:Status, info:&str) -> Offer<'a> {
+        Offer{ status, version:default_version, downloadlink:String::new(), mirrors:vec![], sha256:String::new(), size:0, downgrade:false, delta:None, info:String::from(info) }
+    }
+}
+
+// Why a client can't install a version, with the explanation sent to it
+enum Unsupported{
+    Updater(String),
+    Os(String),
+    Hardware(String),
+}
+
+impl Unsupported{
+    fn info(&self) -> &str {
+        match self {
+            Unsupported::Updater(info) | Unsupported::Os(info) | Unsupported::Hardware(info) => info
+        }
+    }
+}
+
+// The build of a version the client downloads, if its updater, OS and hardware can run it
+fn check_client<'a>(version:&'a Version, request:&Request) -> Result<Option<&'a SysRequirements>, Unsupported> {
+    if request.updaterversion < version.min_updater_version {
+        return Err(Unsupported::Updater(format!("{} requires updater {}", version.number(), version.min_updater_version)));
+    }
+    let build = os::find_build(&request.os, &version.builds).map_err(|reason| Unsupported::Os(format!("{} {}", version.number(), reason)))?;
+    hardware::check(&request.hw, &version.hardware).map_err(|missing| Unsupported::Hardware(format!("{} requires {}", version.number(), missing)))?;
+    Ok(build)
+}
+
+// Picks the version the client should move to, with the delta patch from the client's version
+// when the catalog has one
+pub fn select_version<'a>(versions:&'a Versions, request:&Request, default_version:&'a Version) -> Offer<'a> {
+    let mut offer = choose_version(versions, request, default_version);
+    if offer.status == Status::ok {
+        offer.delta = delta::find_delta(offer.version, request).cloned();
+    }
+    offer
+}
+
+// The newest version on the client's channel that is offered to it, installable by its updater
+// and runs on its OS and hardware, or the rollback target when the client runs a halted version
+// and nothing newer is available. Clients that already run the newest version get nothing.
+fn choose_version<'a>(versions:&'a Versions, request:&Request, default_version:&'a Version) -> Offer<'a> {
+    let channel = versions.channel(&request.channel);
+    let current = parse_number(&request.version);
+
+    let mut os_unsupported = vec![];
+    let mut hw_unsupported = vec![];
+    let mut updater_unsupported = vec![];
+    let candidates = channel
+        .iter()
+        .filter(|version| !version.halted && rollout::is_offered(version, request))
+        .take_while(|version| current.is_none_or(|current| version.key() > current));
+    for version in candidates {
+        let build = match check_client(version, request) {
+            Ok(build) => build,
+            Err(Unsupported::Updater(reason)) => {
+                updater_unsupported.push(reason);
+                continue;
+            },
+            Err(Unsupported::Os(reason)) => {
+                os_unsupported.push(reason);
+                continue;
+            },
+            Err(Unsupported::Hardware(reason)) => {
+                hw_unsupported.push(reason);
+                continue;
+            }
+        };
+
+        if !os_unsupported.is_empty() {
+            let info = format!("{}, {} is the last version supporting this OS", os_unsupported.join("; "), version.number());
+            return Offer::new(Status::errorosnotsupported, version, build, info);
+        }
+        let mut info = format!("update to {}", version.number());
+        if !hw_unsupported.is_empty() {
+            info = format!("{info}, newer versions need unsupported hardware: {}", hw_unsupported.join("; "));
+        }
+        return Offer::new(Status::ok, version, build, info);
+    }
+
+    // a rollback target the client can't run is no way back, the client keeps its version
+    if let Some(target) = current.and_then(|current| halted_rollback(channel, current)) {
+        match check_client(target, request) {
+            Ok(build) => {
+                let mut offer = Offer::new(Status::ok, target, build, format!("rollback to {}", target.number()));
+                offer.downgrade = true;
+                return offer;
+            },
+            Err(reason) => println!("Not rolling back to {}: {}", target.number(), reason.info())
+        }
+    }
+
+    if !os_unsupported.is_empty() {
+        // nothing newer runs on this OS, point the client at the last version that still does,
+        // if that one is still an update for it and it's in the version's rollout
+        let last_supported = channel
+            .iter()
+            .filter(|version| !version.halted && rollout::is_offered(version, request))
+            .take_while(|version| current.is_none_or(|current| version.key() > current))
+            .find_map(|version| check_client(version, request).ok().map(|build| (version, build)));
+        return match last_supported {
+            Some((version, build)) => {
+                let info = format!("{}, {} is the last version supporting this OS", os_unsupported.join("; "), version.number());
+                Offer::new(Status::errorosnotsupported, version, build, info)
+            },
+            None => Offer::none(default_version, Status::errorosnotsupported, &os_unsupported.join("; "))
+        };
+    }
+
+    if !hw_unsupported.is_empty() {
+        return Offer::none(default_version, Status::errorhwnotsupported, &hw_unsupported.join("; "));
+    }
+    if !updater_unsupported.is_empty() {
+        return Offer::none(default_version, Status::errorunsupportedprotocol, &updater_unsupported.join("; "));
+    }
+    Offer::none(default_version, Status::noupdate, "no update available")
+}
+
+// Answers every component listed in the request from that component's own catalog, on the
+// channel of the product. Unknown app ids get an entry of their own instead of failing the request.
+pub fn check_apps(catalog:&Catalog, request:&Request, default_version:&Version) -> Vec<AppResponse> {
+    request.apps
+        .iter()
+        .map(|app| {
+            let Some(app_catalog) = catalog.component(&app.appid) else {
+                return AppResponse{
+                    appid:app.appid.clone(),
+                    status:Status::errorunknownapplication,
+                    version:String::new(),
+                    info:format!("unknown application {}", app.appid),
+                    downloadlink:String::new()
+                };
+            };
+            let mut app_request = request.clone();
+            app_request.version = app.version.clone();
+            let offer = select_version(&app_catalog.versions, &app_request, default_version);
+            AppResponse{
+                appid:app.appid.clone(),
+                version:if offer.status == Status::noupdate { String::new() } else { offer.version.number() },
+                status:offer.status,
+                info:offer.info,
+                downloadlink:offer.downloadlink
+            }
+        })
+        .collect()
+}
+
+fn halted_rollback(channel:&[Version], current:(i32, i32, i32, i32)) -> Option<&Version> {
+    let bad_version = channel.iter().find(|version| version.halted && version.key() == current)?;
+    rollback_target(channel, bad_version)
+}
+
+pub fn rollback_target<'a>(channel:&'a [Version], bad_version:&Version) -> Option<&'a Version> {
+    if bad_version.rollback.is_empty() {
+        return None;
+    }
+    channel
+        .iter()
+        .find(|version| !version.halted && version.matches(&bad_version.rollback))
+}
+
+// Last good version released before the given one, used when an admin doesn't name a rollback
+// target. The channel can be in any order, admin changes see it as the file has it.
+pub fn last_good_version<'a>(channel:&'a [Version], bad_version:&Version) -> Option<&'a Version> {
+    channel
+        .iter()
+        .filter(|version| !version.halted && version.key() < bad_version.key())
+        .max_by_key(|version| version.key())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{default_request, default_version, Channel};
+    use crate::version::parse_versions;
+
+    // Listed out of order on purpose, loading sorts the channel
+    const CATALOG:&str = r#"{
+        "stable":[],
+        "beta":[],
+        "dev":[
+            {"major":1,"minor":1,"build":0,"patch":0,"count":0,"urls":["https://example.com/1.1.0.tar.gz"]},
+            {"major":1,"minor":2,"build":0,"patch":0,"count":0,"urls":["https://example.com/1.2.0.tar.gz"], "halted":true, "rollback":"1.1.5"},
+            {"major":1,"minor":1,"build":5,"patch":0,"count":0,"urls":["https://example.com/1.1.5.tar.gz"]},
+            {"major":1,"minor":1,"build":8,"patch":0,"count":0,"urls":["https://example.com/1.1.8.tar.gz"], "halted":true}
+        ],
+        "canary":[],
+        "extended":[]
+    }"#;
+
+    fn client(version:&str) -> Request {
+        let mut request = default_request();
+        request.channel = Channel::Dev;
+        request.version = String::from(version);
+        request
+    }
+
+    #[test]
+    fn halted_versions_are_skipped() {
+        let versions = parse_versions(CATALOG).unwrap();
+        let default_version = default_version();
+        let offer = select_version(&versions, &client("1.0.0"), &default_version);
+        assert!(offer.status == Status::ok);
+        assert_eq!(offer.version.number(), "1.1.5.0");
+        assert!(!offer.downgrade);
+    }
+
+    #[test]
+    fn clients_on_a_halted_version_are_rolled_back() {
+        let versions = parse_versions(CATALOG).unwrap();
+        let default_version = default_version();
+        let offer = select_version(&versions, &client("1.2.0"), &default_version);
+        assert!(offer.status == Status::ok);
+        assert_eq!(offer.version.number(), "1.1.5.0");
+        assert_eq!(offer.downloadlink, "https://example.com/1.1.5.tar.gz");
+        assert!(offer.downgrade);
+
+        // no rollback target named, the client keeps its version
+        let offer = select_version(&versions, &client("1.1.8"), &default_version);
+        assert!(offer.status == Status::noupdate);
+    }
+
+    // 1.3.0 is the bad release, 1.2.5 was halted before it and 1.2.0 is the last good version
+    fn rollback_catalog(rollback:&str) -> Versions {
+        parse_versions(&format!(r#"{{
+            "stable":[], "beta":[], "canary":[], "extended":[],
+            "dev":[
+                {{"major":1,"minor":3,"build":0,"patch":0,"count":0,"urls":["https://example.com/1.3.0.tar.gz"], "halted":true, "rollback":"{rollback}"}},
+                {{"major":1,"minor":2,"build":5,"patch":0,"count":0,"urls":["https://example.com/1.2.5.tar.gz"], "halted":true}},
+                {{"major":1,"minor":2,"build":0,"patch":0,"count":0,"urls":["https://example.com/1.2.0.tar.gz"]}},
+                {{"major":1,"minor":1,"build":0,"patch":0,"count":0,"urls":["https://example.com/1.1.0.tar.gz"]}}
+            ]
+        }}"#)).unwrap()
+    }
+
+    #[test]
+    fn declared_rollback_target() {
+        let versions = rollback_catalog("1.1.0");
+        let default_version = default_version();
+        let offer = select_version(&versions, &client("1.3.0"), &default_version);
+        assert!(offer.status == Status::ok && offer.downgrade);
+        assert_eq!(offer.version.number(), "1.1.0.0");
+        assert_eq!(offer.downloadlink, "https://example.com/1.1.0.tar.gz");
+
+        // clients on other versions aren't rolled back
+        let offer = select_version(&versions, &client("1.0.0"), &default_version);
+        assert!(offer.status == Status::ok && !offer.downgrade);
+        assert_eq!(offer.version.number(), "1.2.0.0");
+    }
+
+    // An admin rollback without a target stores the last good version as the target
+    #[test]
+    fn rollback_without_a_target_goes_to_the_last_good_version() {
+        let versions = rollback_catalog("");
+        let channel = versions.channel(&Channel::Dev);
+        assert_eq!(last_good_version(channel, &channel[0]).map(Version::number).as_deref(), Some("1.2.0.0"));
+        assert_eq!(rollback_target(channel, &channel[0]).map(Version::number), None);
+
+        let versions = rollback_catalog("1.2.0.0");
+        let default_version = default_version();
+        let offer = select_version(&versions, &client("1.3.0"), &default_version);
+        assert!(offer.status == Status::ok && offer.downgrade);
+        assert_eq!(offer.version.number(), "1.2.0.0");
+        assert_eq!(offer.downloadlink, "https://example.com/1.2.0.tar.gz");
+    }
+
+    // The rollback target goes through the same checks as any other offer
+    #[test]
+    fn rollback_target_the_client_cannot_run() {
+        let versions = parse_versions(r#"{
+            "stable":[], "beta":[], "canary":[], "extended":[],
+            "dev":[
+                {"major":1,"minor":2,"build":0,"patch":0,"count":0,"urls":["https://example.com/1.2.0.tar.gz"], "halted":true, "rollback":"1.1.5"},
+                {"major":1,"minor":1,"build":5,"patch":0,"count":0,"urls":["https://example.com/1.1.5.tar.gz"], "hardware":{"instructions":["avx"]},
+                    "builds":[{"platform":"Windows","arch":"x86_64","min_os_version":"10.0","server":"https://example.com/1.1.5.exe"}]}
+            ]
+        }"#).unwrap();
+        let default_version = default_version();
+
+        let mut request = client("1.2.0");
+        request.os.platform = String::from("Windows");
+        request.os.arch = String::from("x86_64");
+        request.os.version = String::from("10.0.19045");
+        let offer = select_version(&versions, &request, &default_version);
+        assert!(offer.status == Status::ok && offer.downgrade);
+        assert_eq!(offer.downloadlink, "https://example.com/1.1.5.exe");
+
+        // no avx: the client keeps its version
+        request.hw.avx = 0;
+        let offer = select_version(&versions, &request, &default_version);
+        assert!(offer.status == Status::noupdate);
+        assert!(!offer.downgrade && offer.downloadlink.is_empty());
+
+        let mut request = client("1.2.0");
+        request.os.platform = String::from("Linux");
+        request.os.arch = String::from("x86");
+        // no Linux build: the client keeps its version
+        let offer = select_version(&versions, &request, &default_version);
+        assert!(offer.status == Status::noupdate);
+        assert!(!offer.downgrade && offer.downloadlink.is_empty());
+    }
+
+    const HARDWARE_CATALOG:&str = r#"{
+        "stable":[], "beta":[], "canary":[], "extended":[],
+        "dev":[
+            {"major":1,"minor":3,"build":0,"patch":0,"count":0,"urls":["https://example.com/1.3.0.tar.gz"], "hardware":{"instructions":["avx"]}},
+            {"major":1,"minor":2,"build":0,"patch":0,"count":0,"urls":["https://example.com/1.2.0.tar.gz"], "hardware":{"physmemory":8}},
+            {"major":1,"minor":1,"build":0,"patch":0,"count":0,"urls":["https://example.com/1.1.0.tar.gz"]}
+        ]
+    }"#;
+
+    #[test]
+    fn newer_versions_need_unsupported_hardware() {
+        let versions = parse_versions(HARDWARE_CATALOG).unwrap();
+        let default_version = default_version();
+        let mut request = client("1.0.0");
+        (request.hw.avx, request.hw.physmemory) = (0, 4);
+        let offer = select_version(&versions, &request, &default_version);
+        assert!(offer.status == Status::ok);
+        assert_eq!(offer.version.number(), "1.1.0.0");
+        assert_eq!(offer.info, "update to 1.1.0.0, newer versions need unsupported hardware: 1.3.0.0 requires avx; 1.2.0.0 requires 8GB of physical memory");
+
+        request.hw.physmemory = 8;
+        let offer = select_version(&versions, &request, &default_version);
+        assert_eq!(offer.version.number(), "1.2.0.0");
+    }
+
+    #[test]
+    fn no_version_runs_on_the_hardware() {
+        let versions = parse_versions(HARDWARE_CATALOG).unwrap();
+        let default_version = default_version();
+        let mut request = client("1.1.0");
+        (request.hw.avx, request.hw.physmemory) = (0, 4);
+        let offer = select_version(&versions, &request, &default_version);
+        assert!(offer.status == Status::errorhwnotsupported);
+        assert_eq!(offer.info, "1.3.0.0 requires avx; 1.2.0.0 requires 8GB of physical memory");
+        assert!(offer.downloadlink.is_empty());
+    }
+
+    const UPDATER_CATALOG:&str = r#"{
+        "stable":[], "beta":[], "canary":[], "extended":[],
+        "dev":[
+            {"major":1,"minor":2,"build":0,"patch":0,"count":0,"urls":["https://example.com/1.2.0.tar.gz"], "min_updater_version":2.5},
+            {"major":1,"minor":1,"build":0,"patch":0,"count":0,"urls":["https://example.com/1.1.0.tar.gz"]}
+        ]
+    }"#;
+
+    #[test]
+    fn minimum_updater_version() {
+        let versions = parse_versions(UPDATER_CATALOG).unwrap();
+        let default_version = default_version();
+        let offer_for = |version:&str, updaterversion:f32| {
+            let mut request = client(version);
+            request.updaterversion = updaterversion;
+            let offer = select_version(&versions, &request, &default_version);
+            (offer.status, offer.version.number(), offer.info)
+        };
+
+        // below: the older version is still an update, then there's nothing to install
+        let (status, version, info) = offer_for("1.0.0", 2.4);
+        assert!(status == Status::ok);
+        assert_eq!((version.as_str(), info.as_str()), ("1.1.0.0", "update to 1.1.0.0"));
+        let (status, _, info) = offer_for("1.1.0", 2.4);
+        assert!(status == Status::errorunsupportedprotocol);
+        assert_eq!(info, "1.2.0.0 requires updater 2.5");
+
+        // at and above
+        for updaterversion in [2.5, 3.0] {
+            let (status, version, _) = offer_for("1.1.0", updaterversion);
+            assert!(status == Status::ok);
+            assert_eq!(version, "1.2.0.0");
+        }
+    }
+
+    // 2.0 needs Windows 11, 1.5 runs on Windows 10 but not without avx
+    const OS_CATALOG:&str = r#"{
+        "stable":[], "beta":[], "canary":[], "extended":[],
+        "dev":[
+            {"major":2,"minor":0,"build":0,"patch":0,"count":0,"urls":[],
+                "builds":[{"platform":"Windows","arch":"x86_64","min_os_version":"10.0.22000","server":"https://example.com/2.0.0.exe"}]},
+            {"major":1,"minor":5,"build":0,"patch":0,"count":0,"urls":[], "hardware":{"instructions":["avx"]}, "rollout":50,
+                "builds":[{"platform":"Windows","arch":"x86_64","min_os_version":"10.0","server":"https://example.com/1.5.0.exe"}]}
+        ]
+    }"#;
+
+    fn windows_10_client(version:&str, installid:&str) -> Request {
+        let mut request = client(version);
+        request.os.platform = String::from("Windows");
+        request.os.arch = String::from("x86_64");
+        request.os.version = String::from("10.0.19045");
+        request.installid = String::from(installid);
+        request.hw.avx = 1;
+        request
+    }
+
+    // a client in the rollout of 1.5, and one outside of it
+    fn installs(version:&Version) -> (String, String) {
+        let id = |inside:bool| (0..).map(|index| format!("install-{index}")).find(|id| (rollout::bucket(id, version) < 50) == inside).unwrap();
+        (id(true), id(false))
+    }
+
+    #[test]
+    fn last_version_supporting_the_os() {
+        let versions = parse_versions(OS_CATALOG).unwrap();
+        let default_version = default_version();
+        let (inside, outside) = installs(&versions.channel(&Channel::Dev)[1]);
+
+        let offer = select_version(&versions, &windows_10_client("1.0.0", &inside), &default_version);
+        assert!(offer.status == Status::errorosnotsupported);
+        assert_eq!(offer.version.number(), "1.5.0.0");
+        assert_eq!(offer.info, "2.0.0.0 requires Windows 10.0.22000, 1.5.0.0 is the last version supporting this OS");
+
+        // not newer than what the client runs
+        for current in ["1.5.0", "1.6.0"] {
+            let offer = select_version(&versions, &windows_10_client(current, &inside), &default_version);
+            assert!(offer.status == Status::errorosnotsupported);
+            assert_eq!(offer.info, "2.0.0.0 requires Windows 10.0.22000");
+            assert!(offer.downloadlink.is_empty());
+        }
+
+        // not rolled out to the client
+        let offer = select_version(&versions, &windows_10_client("1.0.0", &outside), &default_version);
+        assert!(offer.status == Status::errorosnotsupported);
+        assert_eq!(offer.info, "2.0.0.0 requires Windows 10.0.22000");
+        assert!(offer.downloadlink.is_empty());
+
+        // runs on the OS but not on the client's hardware
+        let mut request = windows_10_client("1.0.0", &inside);
+        request.hw.avx = 0;
+        let offer = select_version(&versions, &request, &default_version);
+        assert!(offer.status == Status::errorosnotsupported);
+        assert_eq!(offer.info, "2.0.0.0 requires Windows 10.0.22000");
+        assert!(offer.downloadlink.is_empty());
+    }
+
+    #[test]
+    fn package_hash_comes_with_the_package() {
+        let versions = parse_versions(r#"{
+            "stable":[], "beta":[], "canary":[], "extended":[],
+            "dev":[{"major":2,"minor":0,"build":0,"patch":0,"count":0,"urls":["https://example.com/2.0.0.tar.gz"],"sha256":"aa","size":10,
+                "builds":[{"platform":"Windows","arch":"x86_64","min_os_version":"10.0","server":"https://example.com/2.0.0.exe","sha256":"bb","size":20},
+                    {"platform":"Linux","arch":"x86","min_os_version":"0","server":""}]}]
+        }"#).unwrap();
+        let default_version = default_version();
+        let mut request = client("1.0.0");
+        request.os.platform = String::from("Windows");
+        request.os.arch = String::from("x86_64");
+        request.os.version = String::from("10.0.19045");
+        let offer = select_version(&versions, &request, &default_version);
+        assert_eq!((offer.downloadlink.as_str(), offer.sha256.as_str(), offer.size), ("https://example.com/2.0.0.exe", "bb", 20));
+
+        // a build without its own package gets the version's
+        request.os.platform = String::from("Linux");
+        request.os.arch = String::from("x86");
+        request.os.version = String::new();
+        let offer = select_version(&versions, &request, &default_version);
+        assert_eq!((offer.downloadlink.as_str(), offer.sha256.as_str(), offer.size), ("https://example.com/2.0.0.tar.gz", "aa", 10));
+    }
+}
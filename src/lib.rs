@@ -0,0 +1,355 @@
+// The update server as a library: the protocol types below, the catalogs, sessions and
+// endpoints, and Server, which answers an HttpRequest with a Response without any network.
+// The updateserver binary only adds the listener, TLS and the environment.
+pub mod version;
+pub mod latest;
+pub mod session;
+pub mod rollout;
+pub mod admin;
+pub mod hardware;
+pub mod os;
+pub mod protocol;
+pub mod omaha;
+pub mod omaha_json;
+pub mod encoding;
+pub mod catalog;
+pub mod artifacts;
+pub mod mirror;
+pub mod delta;
+pub mod deltagen;
+pub mod signing;
+pub mod cup;
+pub mod connection;
+pub mod compression;
+pub mod query;
+pub mod router;
+pub mod tls;
+pub mod http;
+pub mod endpoints;
+pub mod server;
+
+use std::fmt;
+use random_string::generate;
+use serde::{Serialize, Deserialize};
+use crate::version::Version;
+use crate::hardware::HardwareRequirements;
+use crate::delta::Delta;
+
+pub use crate::server::{Server, ServerConfig, ServerState};
+
+pub const VERSIONS_PATH:&str = "versions.json";
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum Platform {
+    Linux,
+    MacOS,
+    Windows,
+    Unknown
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[allow(non_camel_case_types)]
+pub enum Architecture{
+    Arm,
+    Arm64,
+    x86,
+    x86_64,
+    x64
+}
+
+#[derive(Serialize, Deserialize, PartialEq,Clone)]
+pub struct Hardware{
+    pub sse:i32,
+    sse2:i32,
+    sse41:i32,
+    sse42:i32,
+    sse3:i32,
+    pub avx:i32,
+    pub physmemory:i32 // Physical memory available to the client, if unknown, assume its the size of the latest release +2GB
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct OperatingSystem{
+    pub platform:String,
+    pub sp:String, // Service Pack
+    pub arch:String,
+    pub dedup:String, // used to dedup user count
+    #[serde(default)]
+    pub version:String, // OS version, e.g "10.0.19045" or "14.2"
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum Channel{
+    Stable,
+    Beta,
+    Dev,
+    Canary,
+    Extended
+}
+
+impl fmt::Display for Channel{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let channel_string = match self{
+            Channel::Stable => "Stable",
+            Channel::Beta => "Beta",
+            Channel::Dev => "Dev",
+            Channel::Canary => "Canary",
+            Channel::Extended => "Extended",
+        };
+        write!(f, "{}", channel_string)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct Request{
+    pub updater:String, // client software
+    pub acceptformat:String,
+    pub hw:Hardware,
+    pub ismachine:i32, // used system-wide or only for a single user
+    pub os:OperatingSystem,
+    pub protocol:f32, // the version of mini omaha protocol
+    pub requestid:String,
+    pub sessionid:String,
+    pub channel:Channel,
+    pub updaterversion:f32,
+    #[serde(default)]
+    pub version:String, // version currently installed on the client, e.g "0.3.1"
+    #[serde(default)]
+    pub installid:String, // stable per-install id, used to place the client in rollout buckets
+    #[serde(default)]
+    pub apps:Vec<AppRequest>, // bundled components checked in the same request, each with its own catalog
+    #[serde(default)]
+    pub region:String, // region hint, mirrors in the same region are tried first
+    #[serde(default)]
+    pub redirect:bool, // answer /download with a 302 to the selected mirror
+    #[serde(skip)]
+    pub appid:String, // Omaha app the request is about, each app has its own session
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct AppRequest{
+    pub appid:String,
+    pub version:String, // version of the component installed on the client
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TimerObject{
+    pub elapsed_days:i32
+}
+
+// A per-platform build of a catalog entry and the oldest OS it runs on
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SysRequirements{
+    pub platform:Platform,
+    pub arch:Architecture,
+    pub min_os_version:String, // dotted version, compared component by component
+    #[serde(default)]
+    pub min_sp:i32, // minimum service pack, 0 => none required
+    pub server:String, // download url of this build
+    #[serde(default)]
+    pub sha256:String, // hex digest of the package at server
+    #[serde(default)]
+    pub size:u64, // size of the package at server in bytes
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+#[allow(non_camel_case_types)]
+pub enum Status{    // Used in status requests/checks for a particular session
+    ok,
+    noupdate,
+    errorinternal,
+    errorhash,
+    errorosnotsupported,
+    errorhwnotsupported,
+    errorunsupportedprotocol,
+    errorunknownapplication,
+    errorunknownproduct,
+    errorinvalidsession, // the session doesn't exist (any more) or doesn't allow the reported action
+    updatecomplete,
+    updateabandoned,
+}
+
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize)]
+pub struct Manifest{
+    pub arguments:String,
+    pub run:String, // basically the installer (this will need work)
+    pub version:version::Version,
+    pub url:String // the download url for the new version
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize)]
+pub struct Response {
+    pub daystart: TimerObject,
+    pub name: String,
+    pub status: Status,
+    pub manifest:Manifest,
+}
+
+// List of actions that can be taken based on specific response
+// Only two actions supported right now:
+// download -> download after verification
+// abandon -> no more responses,
+// retry -> for failures
+#[derive(Serialize, Deserialize,PartialEq, Clone)]
+#[allow(non_camel_case_types)]
+pub enum Action{
+    download,
+    abandon,
+    retry,
+    latest,
+    complete,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub enum EventType {
+    Install,
+    Update,
+    Uninstall,
+    Download,
+    Complete,
+    None
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LatestResponse{
+    pub actions:Vec<Action>,
+    pub info:String,
+    pub status:Status,
+    pub version: String,
+    pub sessionid:String,
+    pub requestid:String,
+    pub downgrade:bool, // set when the client is rolled back from a halted version
+    pub protocol:f32, // protocol version the server answers with
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub sha256:String, // hex digest of the full package of version
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub size:u64, // size of the full package in bytes
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub apps:Vec<AppResponse>, // one entry per component listed in Request.apps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta:Option<Delta> // patch from the client's version, preferred over the full package
+}
+
+// Components are downloaded straight from the link, they don't go through the download/status session
+#[derive(Serialize, Deserialize)]
+pub struct AppResponse{
+    pub appid:String,
+    pub status:Status,
+    pub version:String,
+    pub info:String,
+    pub downloadlink:String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DownloadResponse{
+    pub actions:Vec<Action>,
+    pub info:String,
+    pub status:Status,
+    pub sessionid:String,
+    pub requestid:String,
+    pub downloadlink:String,
+    pub downgrade:bool,
+    pub protocol:f32,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub sha256:String, // hex digest of the package at downloadlink and every mirror
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub size:u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mirrors:Vec<String>, // every mirror in the order to try them, downloadlink is the first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta:Option<Delta> // patch from the client's version, downloadlink stays the full package to fall back to
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StatusResponse{
+    pub sessionid:String,
+    pub requestid:String,
+    pub status:Status,
+    pub protocol:f32
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct StatusRequest{
+    pub request:Request,
+    pub eventtype:EventType,
+    pub action:Action,
+    pub result:i32, // 0 => error, 1 => success, 2 => cancelled
+    #[serde(default)]
+    pub mirror:String, // mirror the result is about, failing mirrors are demoted
+    #[serde(default)]
+    pub delta:bool, // the result is about applying the delta patch, a failure switches the session to the full package
+}
+
+// Sizes the catalog doesn't know are left out of responses
+pub fn is_zero(size:&u64) -> bool {
+    *size == 0
+}
+
+pub fn generate_id() -> String {
+    let character_set ="0123456789abcdefghijklmnopqrstuvwxyz";
+    let generated_id = generate(25,character_set); // 128 bits of entropy
+    generated_id
+}
+
+// Stands in for the version of clients and products that have none yet
+pub fn default_version() -> Version {
+    version::Version{
+        major:0,
+        minor:0,
+        build:0,
+        patch:0,
+        count:0,
+        urls:vec![],
+        sha256:String::new(),
+        size:0,
+        rollout:100,
+        halted:false,
+        rollback:String::from(""),
+        hardware:HardwareRequirements::default(),
+        builds:vec![],
+        min_updater_version:0.0,
+        mirrors:vec![],
+        deltas:vec![]
+    }
+}
+
+// Request with every field empty, the base that query parameters are applied to
+pub fn default_request() -> Request {
+    Request{
+        updater:String::from(""), // client software
+        acceptformat:String::from(""),
+        hw: Hardware {
+            sse:-1,
+            sse2:-1,
+            sse41:-1,
+            sse42:-1,
+            sse3:-1,
+            avx:-1,
+            physmemory:-1
+        },
+        ismachine:0, // used system-wide or only for a single user
+        os: OperatingSystem{
+            platform:String::from(""),
+            sp:String::from(""), // Service Pack
+            arch:String::from(""),
+            dedup:String::from(""), // used to dedup user count
+            version:String::from(""),
+        },
+        protocol:1.0, // the version of mini omaha protocol
+        requestid:String::from(""),
+        sessionid:String::from(""),
+        channel: Channel::Dev,
+        updaterversion:0.0,
+        version:String::from(""),
+        installid:String::from(""),
+        apps:vec![],
+        region:String::from(""),
+        redirect:false,
+        appid:String::from("")
+    }
+}
+
@@ -0,0 +1,105 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+
+// Download mirrors of a package. The download endpoint orders them for every client: healthy
+// mirrors in the client's region first, picked by weight in turns, then the other healthy
+// mirrors, then mirrors clients reported as failing. Failing mirrors stay demoted for a while,
+// the period doubles with every failure reported in a row.
+const DEMOTION_SECS:u64 = 60;
+const MAX_DEMOTION_SECS:u64 = 3600;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mirror{
+    pub url:String,
+    #[serde(default = "default_weight")]
+    pub weight:u32, // share of the clients sent to this mirror first, 0 only serves as a fallback
+    #[serde(default)]
+    pub region:String, // region hint (Request.region) this mirror is close to, empty for any region
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl Mirror{
+    pub fn new(url:&str) -> Mirror {
+        Mirror{ url:String::from(url), weight:default_weight(), region:String::new() }
+    }
+}
+
+struct MirrorHealth{
+    failures:u32, // failures reported in a row
+    demoted_until:Instant,
+}
+
+#[derive(Default)]
+pub struct MirrorState{
+    health:HashMap<String, MirrorHealth>, // keyed by mirror url
+    turn:u64, // round robin counter for the weighted pick
+}
+
+impl MirrorState{
+    pub fn report_failure(&mut self, url:&str){
+        let now = Instant::now();
+        let health = self.health.entry(String::from(url)).or_insert(MirrorHealth{ failures:0, demoted_until:now });
+        health.failures += 1;
+        let demotion = DEMOTION_SECS.saturating_mul(1 << (health.failures - 1).min(16)).min(MAX_DEMOTION_SECS);
+        health.demoted_until = now + Duration::from_secs(demotion);
+        println!("Mirror {} demoted for {}s after {} failures", url, demotion, health.failures);
+    }
+
+    pub fn report_success(&mut self, url:&str){
+        if self.health.remove(url).is_some() {
+            println!("Mirror {} is healthy again", url);
+        }
+    }
+
+    fn is_demoted(&self, url:&str, now:Instant) -> bool {
+        self.health.get(url).is_some_and(|health| health.demoted_until > now)
+    }
+
+    // Urls of the mirrors in the order the client should try them
+    pub fn order(&mut self, mirrors:&[Mirror], region:&str) -> Vec<String> {
+        let now = Instant::now();
+        let (mut demoted, healthy):(Vec<&Mirror>, Vec<&Mirror>) = mirrors.iter().partition(|mirror| self.is_demoted(&mirror.url, now));
+        let (local, remote):(Vec<&Mirror>, Vec<&Mirror>) = healthy
+            .into_iter()
+            .partition(|mirror| !region.is_empty() && mirror.region.eq_ignore_ascii_case(region));
+
+        let turn = self.turn;
+        self.turn = self.turn.wrapping_add(1);
+
+        let mut ordered = vec![];
+        for mut group in [local, remote] {
+            group.sort_by_key(|mirror| Reverse(mirror.weight));
+            if let Some(first) = weighted_pick(&group, turn) {
+                let first = group.remove(first);
+                group.insert(0, first);
+            }
+            ordered.extend(group);
+        }
+        // the mirror that comes back first goes first
+        demoted.sort_by_key(|mirror| self.health.get(&mirror.url).map(|health| health.demoted_until));
+        ordered.extend(demoted);
+
+        ordered.into_iter().map(|mirror| mirror.url.clone()).collect()
+    }
+}
+
+// Walks the weights so that over total_weight turns every mirror comes first weight times
+fn weighted_pick(mirrors:&[&Mirror], turn:u64) -> Option<usize> {
+    let total:u64 = mirrors.iter().map(|mirror| mirror.weight as u64).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut slot = turn % total;
+    mirrors.iter().position(|mirror| {
+        if slot < mirror.weight as u64 {
+            return true;
+        }
+        slot -= mirror.weight as u64;
+        false
+    })
+}
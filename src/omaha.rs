@@ -0,0 +1,544 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::{self, Visitor};
+use crate::{cup, endpoints, generate_id, latest, protocol, Action, Channel, EventType, Hardware, OperatingSystem, Request, Status, StatusRequest, TimerObject};
+use crate::session::{new_session, update_current_action, update_session_actions, SessionManager};
+use crate::version::{Version, Versions};
+use crate::catalog::{Catalog, Products};
+use crate::mirror::MirrorState;
+use crate::cup::{CupKey, CupRequest, NonceCache};
+use crate::http::{create_response_with_type, with_header, HttpRequest, Response};
+
+// Omaha v3 (XML) support, so the stock Chromium updater can talk to this server. The
+// request types and the update logic are shared with the Omaha 4 JSON endpoint in omaha_json.
+// Omaha has no separate download step: an <updatecheck> maps onto /latest and /download at
+// once and the response carries the package urls, <event> elements map onto /status.
+// Each app is answered from the catalog of its product when the app id is the product's own, or of
+// its component when the app id names one. Other app ids get error-unknownApplication.
+
+const OMAHA_PROTOCOL:&str = "3.0";
+const DAYSTART_EPOCH_DAYS:u64 = 13514; // 2007-01-01, Omaha counts elapsed_days from there
+
+// Omaha event types that end an update: install, update and uninstall. Download events
+// (1, 5, 13, 14) and the other types only report progress on the way there.
+const COMPLETION_EVENTS:[i32; 3] = [2, 3, 4];
+const ERROR_RESULTS:[i32; 5] = [0, 5, 6, 8, 10]; // error, installer errors and a failed handoff
+const CANCELLED_RESULT:i32 = 4;
+const DIFF_FAILED:i32 = 0; // diffresult of a differential update that didn't apply
+
+fn unknown_hw() -> i32 {
+    -1
+}
+
+fn no_diff() -> i32 {
+    -1
+}
+
+// XML attributes arrive as strings ("1", "true"), Omaha 4 JSON sends numbers and booleans
+fn flag<'de, D: Deserializer<'de>>(deserializer:D) -> Result<i32, D::Error> {
+    struct FlagVisitor;
+
+    impl Visitor<'_> for FlagVisitor {
+        type Value = i32;
+
+        fn expecting(&self, formatter:&mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a number, a boolean or a string holding either")
+        }
+
+        fn visit_bool<E: de::Error>(self, value:bool) -> Result<i32, E> {
+            Ok(value as i32)
+        }
+
+        fn visit_i64<E: de::Error>(self, value:i64) -> Result<i32, E> {
+            i32::try_from(value).map_err(E::custom)
+        }
+
+        fn visit_u64<E: de::Error>(self, value:u64) -> Result<i32, E> {
+            i32::try_from(value).map_err(E::custom)
+        }
+
+        fn visit_f64<E: de::Error>(self, value:f64) -> Result<i32, E> {
+            Ok(value as i32)
+        }
+
+        fn visit_str<E: de::Error>(self, value:&str) -> Result<i32, E> {
+            match value.trim() {
+                "true" => Ok(1),
+                "false" | "" => Ok(0),
+                number => number.parse::<f64>().map(|number| number as i32).map_err(E::custom)
+            }
+        }
+    }
+
+    deserializer.deserialize_any(FlagVisitor)
+}
+
+// Field names carry the XML attribute name, the aliases are the Omaha 4 JSON keys
+#[derive(Deserialize)]
+pub struct OmahaRequest{
+    #[serde(rename = "@protocol", alias = "protocol")]
+    pub protocol:String,
+    #[serde(rename = "@updater", default)]
+    updater:String, // Omaha 4 JSON sends both "@updater" and "updater", only the first is read
+    #[serde(rename = "@updaterversion", alias = "updaterversion", default)]
+    updaterversion:String,
+    #[serde(rename = "@ismachine", alias = "ismachine", default, deserialize_with = "flag")]
+    ismachine:i32,
+    #[serde(rename = "@sessionid", alias = "sessionid", default)]
+    sessionid:String,
+    #[serde(rename = "@requestid", alias = "requestid", default)]
+    requestid:String,
+    #[serde(rename = "@userid", alias = "userid", default)]
+    userid:String, // only sent by clients that count users by id (dedup="uid")
+    hw:Option<OmahaHardware>,
+    os:Option<OmahaOs>,
+    #[serde(rename = "app", alias = "apps", default)]
+    apps:Vec<OmahaApp>,
+}
+
+#[derive(Deserialize)]
+struct OmahaHardware{
+    #[serde(rename = "@sse", alias = "sse", default = "unknown_hw", deserialize_with = "flag")]
+    sse:i32,
+    #[serde(rename = "@sse2", alias = "sse2", default = "unknown_hw", deserialize_with = "flag")]
+    sse2:i32,
+    #[serde(rename = "@sse3", alias = "sse3", default = "unknown_hw", deserialize_with = "flag")]
+    sse3:i32,
+    #[serde(rename = "@sse41", alias = "sse41", default = "unknown_hw", deserialize_with = "flag")]
+    sse41:i32,
+    #[serde(rename = "@sse42", alias = "sse42", default = "unknown_hw", deserialize_with = "flag")]
+    sse42:i32,
+    #[serde(rename = "@avx", alias = "avx", default = "unknown_hw", deserialize_with = "flag")]
+    avx:i32,
+    #[serde(rename = "@physmemory", alias = "physmemory", default = "unknown_hw", deserialize_with = "flag")]
+    physmemory:i32,
+}
+
+#[derive(Deserialize)]
+struct OmahaOs{
+    #[serde(rename = "@platform", alias = "platform", default)]
+    platform:String,
+    #[serde(rename = "@version", alias = "version", default)]
+    version:String,
+    #[serde(rename = "@sp", alias = "sp", default)]
+    sp:String,
+    #[serde(rename = "@arch", alias = "arch", default)]
+    arch:String,
+}
+
+#[derive(Deserialize)]
+struct OmahaApp{
+    #[serde(rename = "@appid", alias = "appid")]
+    appid:String,
+    #[serde(rename = "@version", alias = "version", default)]
+    version:String,
+    #[serde(rename = "@ap", alias = "ap", default)]
+    ap:String, // additional parameters, carries the channel
+    #[serde(rename = "@iid", alias = "iid", default)]
+    iid:String,
+    updatecheck:Option<OmahaUpdateCheck>,
+    ping:Option<OmahaPing>,
+    #[serde(rename = "event", alias = "events", default)]
+    events:Vec<OmahaEvent>,
+}
+
+#[derive(Deserialize)]
+struct OmahaUpdateCheck{
+    #[serde(rename = "@updatedisabled", alias = "updatedisabled", default, deserialize_with = "flag")]
+    updatedisabled:i32,
+}
+
+#[derive(Deserialize)]
+struct OmahaPing{}
+
+#[derive(Deserialize)]
+struct OmahaEvent{
+    #[serde(rename = "@eventtype", alias = "eventtype", deserialize_with = "flag")]
+    eventtype:i32,
+    #[serde(rename = "@eventresult", alias = "eventresult", default, deserialize_with = "flag")]
+    eventresult:i32,
+    #[serde(rename = "@errorcode", alias = "errorcode", default, deserialize_with = "flag")]
+    errorcode:i32,
+    #[serde(rename = "@diffresult", alias = "diffresult", default = "no_diff", deserialize_with = "flag")]
+    diffresult:i32, // only sent when a differential update was tried
+}
+
+// Protocol independent answer for one app, written out as XML here and as JSON by omaha_json
+pub struct AppResult{
+    pub appid:String,
+    pub status:&'static str,
+    pub updatecheck:Option<UpdateCheckResult>,
+    pub ping:bool,
+    pub events:usize, // number of events to acknowledge
+}
+
+pub struct UpdateCheckResult{
+    pub status:&'static str,
+    pub info:String,
+    pub version:String,
+    pub downloadlink:String, // empty unless status is "ok"
+    pub sha256:String, // hex digest of the package, empty when the catalog has none
+    pub size:u64,
+}
+
+impl UpdateCheckResult{
+    // No package to hand out, the status says why
+    fn none(status:&'static str, info:String) -> UpdateCheckResult {
+        UpdateCheckResult{ status, info, version:String::new(), downloadlink:String::new(), sha256:String::new(), size:0 }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename = "response")]
+struct OmahaResponse{
+    #[serde(rename = "@protocol")]
+    protocol:&'static str,
+    #[serde(rename = "@server")]
+    server:&'static str,
+    daystart:OmahaDayStart,
+    #[serde(rename = "app")]
+    apps:Vec<OmahaAppResponse>,
+}
+
+#[derive(Serialize)]
+struct OmahaDayStart{
+    #[serde(rename = "@elapsed_seconds")]
+    elapsed_seconds:u64,
+    #[serde(rename = "@elapsed_days")]
+    elapsed_days:i32,
+}
+
+#[derive(Serialize)]
+struct OmahaAppResponse{
+    #[serde(rename = "@appid")]
+    appid:String,
+    #[serde(rename = "@status")]
+    status:&'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updatecheck:Option<OmahaUpdateCheckResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ping:Option<OmahaAck>,
+    #[serde(rename = "event", skip_serializing_if = "Vec::is_empty")]
+    events:Vec<OmahaAck>,
+}
+
+#[derive(Serialize)]
+struct OmahaUpdateCheckResponse{
+    #[serde(rename = "@status")]
+    status:&'static str,
+    #[serde(rename = "@info", skip_serializing_if = "String::is_empty")]
+    info:String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    urls:Option<OmahaUrls>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manifest:Option<OmahaManifest>,
+}
+
+#[derive(Serialize)]
+struct OmahaUrls{
+    url:Vec<OmahaUrl>,
+}
+
+#[derive(Serialize)]
+struct OmahaUrl{
+    #[serde(rename = "@codebase")]
+    codebase:String,
+}
+
+#[derive(Serialize)]
+struct OmahaManifest{
+    #[serde(rename = "@version")]
+    version:String,
+    packages:OmahaPackages,
+}
+
+#[derive(Serialize)]
+struct OmahaPackages{
+    package:Vec<OmahaPackage>,
+}
+
+#[derive(Serialize)]
+struct OmahaPackage{
+    #[serde(rename = "@name")]
+    name:String,
+    #[serde(rename = "@required")]
+    required:bool,
+    #[serde(rename = "@size", skip_serializing_if = "crate::is_zero")]
+    size:u64,
+    #[serde(rename = "@hash_sha256", skip_serializing_if = "String::is_empty")]
+    hash_sha256:String,
+}
+
+#[derive(Serialize)]
+struct OmahaAck{
+    #[serde(rename = "@status")]
+    status:&'static str,
+}
+
+pub fn status_name(status:&Status) -> &'static str {
+    match status {
+        Status::ok => "ok",
+        Status::noupdate => "noupdate",
+        Status::errorinternal | Status::errorinvalidsession => "error-internal",
+        Status::errorhash => "error-hash",
+        Status::errorosnotsupported => "error-osnotsupported",
+        Status::errorhwnotsupported => "error-hwnotsupported",
+        Status::errorunsupportedprotocol => "error-unsupportedProtocol",
+        Status::errorunknownapplication | Status::errorunknownproduct => "error-unknownApplication",
+        Status::updatecomplete | Status::updateabandoned => "ok",
+    }
+}
+
+// Omaha clients send the channel in the "ap" attribute, e.g "beta" or "x64-stable"
+fn channel_from_ap(ap:&str) -> Channel {
+    let ap = ap.to_ascii_lowercase();
+    if ap.contains("extended") {
+        Channel::Extended
+    } else if ap.contains("canary") {
+        Channel::Canary
+    } else if ap.contains("dev") {
+        Channel::Dev
+    } else if ap.contains("beta") {
+        Channel::Beta
+    } else {
+        Channel::Stable
+    }
+}
+
+// "1.3.36.1" => 1.3, Request.updaterversion only keeps major.minor
+fn updater_version(version:&str) -> f32 {
+    let mut parts = version.split('.');
+    let major = parts.next().unwrap_or("0");
+    let minor = parts.next().unwrap_or("0");
+    format!("{major}.{minor}").parse::<f32>().unwrap_or(0.0)
+}
+
+// Seconds since midnight and days since 2007-01-01
+pub fn daystart() -> (u64, TimerObject) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+    let timer = TimerObject{ elapsed_days:(now / 86400).saturating_sub(DAYSTART_EPOCH_DAYS) as i32 };
+    (now % 86400, timer)
+}
+
+// Rollout buckets need an id that stays with one client. Stock Chromium sends neither iid nor
+// userid (its dedup="cr" is a counting mode, not an id) and a cohort is shared by many clients,
+// so such a client has no stable id and only gets fully rolled out versions.
+fn client_id(omaha_request:&OmahaRequest, app:&OmahaApp) -> String {
+    if app.iid.is_empty() { omaha_request.userid.clone() } else { app.iid.clone() }
+}
+
+fn to_request(omaha_request:&OmahaRequest, app:&OmahaApp, acceptformat:&str) -> Request {
+    let hw = match &omaha_request.hw {
+        Some(hw) => Hardware{ sse:hw.sse, sse2:hw.sse2, sse41:hw.sse41, sse42:hw.sse42, sse3:hw.sse3, avx:hw.avx, physmemory:hw.physmemory },
+        None => Hardware{ sse:-1, sse2:-1, sse41:-1, sse42:-1, sse3:-1, avx:-1, physmemory:-1 }
+    };
+    let os = match &omaha_request.os {
+        Some(os) => OperatingSystem{ platform:os.platform.clone(), sp:os.sp.clone(), arch:os.arch.clone(), dedup:String::new(), version:os.version.clone() },
+        None => OperatingSystem{ platform:String::new(), sp:String::new(), arch:String::new(), dedup:String::new(), version:String::new() }
+    };
+    Request{
+        updater:omaha_request.updater.clone(),
+        acceptformat:String::from(acceptformat),
+        hw,
+        ismachine:omaha_request.ismachine,
+        os,
+        protocol:protocol::MAX_PROTOCOL, // the Omaha protocol version is checked separately
+        requestid:if omaha_request.requestid.is_empty() { generate_id() } else { omaha_request.requestid.clone() },
+        sessionid:if omaha_request.sessionid.is_empty() { generate_id() } else { omaha_request.sessionid.clone() },
+        channel:channel_from_ap(&app.ap),
+        updaterversion:updater_version(&omaha_request.updaterversion),
+        version:app.version.clone(),
+        installid:client_id(omaha_request, app),
+        apps:vec![],
+        region:String::new(),
+        redirect:false,
+        appid:app.appid.clone()
+    }
+}
+
+fn update_check(request:&Request, default_version:&Version, versions:&Versions, session_manager:&mut SessionManager) -> UpdateCheckResult {
+    let offer = latest::select_version(versions, request, default_version);
+    if offer.status != Status::ok {
+        return UpdateCheckResult::none(status_name(&offer.status), offer.info);
+    }
+    // an "ok" without urls would leave the client with nothing to install
+    if offer.downloadlink.is_empty() {
+        println!("Version {} has no package for app {}", offer.version.number(), request.appid);
+        return UpdateCheckResult::none("noupdate", format!("{} has no package for this client", offer.version.number()));
+    }
+
+    // the update session starts in the download phase, events from the client end it
+    if !new_session(session_manager, request) {
+        println!("Failed to create a new session for app {} because session {} already exists", request.appid, request.sessionid);
+        return UpdateCheckResult::none(status_name(&Status::errorinternal), String::from("update session already exists"));
+    }
+    update_current_action(session_manager, request, Action::download);
+    update_session_actions(session_manager, request, vec![Action::abandon, Action::retry]);
+
+    UpdateCheckResult{ status:"ok", info:offer.info, version:offer.version.number(), downloadlink:offer.downloadlink, sha256:offer.sha256, size:offer.size }
+}
+
+fn event_type(eventtype:i32) -> EventType {
+    match eventtype {
+        1 | 5 | 13 | 14 => EventType::Download,
+        2 => EventType::Install,
+        3 => EventType::Update,
+        4 => EventType::Uninstall,
+        _ => EventType::None
+    }
+}
+
+// Events take the same path as a /status report: errors keep the session for a retry (a failed
+// differential update switches it to the full package), a cancellation abandons it and a
+// successful install, update or uninstall completes it. Other successes are progress reports.
+fn record_event(request:&Request, event:&OmahaEvent, default_version:&Version, catalog:&Catalog, session_manager:&mut SessionManager, mirror_state:&mut MirrorState){
+    println!("Omaha event {} result {} (error {}) for session {} app {}", event.eventtype, event.eventresult, event.errorcode, request.sessionid, request.appid);
+    let (action, result) = if ERROR_RESULTS.contains(&event.eventresult) {
+        (Action::retry, 0)
+    } else if event.eventresult == CANCELLED_RESULT {
+        (Action::abandon, 2)
+    } else if COMPLETION_EVENTS.contains(&event.eventtype) {
+        (Action::retry, 1)
+    } else {
+        return;
+    };
+    let mut status_request = StatusRequest{
+        request:request.clone(),
+        eventtype:event_type(event.eventtype),
+        action,
+        result,
+        mirror:String::new(),
+        delta:event.diffresult == DIFF_FAILED
+    };
+    let response = endpoints::handle_status(default_version, catalog, session_manager, mirror_state, &mut status_request);
+    if response.status_code != 200 {
+        println!("Omaha event for session {} app {} was not applied ({})", request.sessionid, request.appid, response.status_code);
+    }
+}
+
+// Stock Omaha clients send their own name as updater ("Omaha", "chromium"), so the product is
+// also looked up by app id. An app id no product knows is an unknown product, not the default one.
+fn product_catalog<'a>(products:&'a Products, updater:&str, appid:&str) -> Option<&'a Catalog> {
+    products.get(updater).or_else(|| products.find_app(appid))
+}
+
+// Runs the update checks, pings and events of every app in the request
+pub fn answer(omaha_request:&OmahaRequest, protocol_ok:bool, acceptformat:&str, default_version:&Version, products:&Products, session_manager:&mut SessionManager, mirror_state:&mut MirrorState) -> Vec<AppResult> {
+    let mut apps = vec![];
+    for app in &omaha_request.apps {
+        let request = to_request(omaha_request, app, acceptformat);
+        let mut result = AppResult{ appid:app.appid.clone(), status:"ok", updatecheck:None, ping:false, events:0 };
+
+        if !protocol_ok {
+            result.status = status_name(&Status::errorunsupportedprotocol);
+            apps.push(result);
+            continue;
+        }
+        let Some(catalog) = product_catalog(products, &omaha_request.updater, &app.appid) else {
+            println!("Omaha request for unknown product {}", app.appid);
+            result.status = status_name(&Status::errorunknownproduct);
+            apps.push(result);
+            continue;
+        };
+
+        // a stray app id must not be answered with the product's package
+        let Some(versions) = catalog.app_versions(&app.appid) else {
+            println!("Omaha request for unknown application {}", app.appid);
+            result.status = status_name(&Status::errorunknownapplication);
+            apps.push(result);
+            continue;
+        };
+
+        if let Some(updatecheck) = &app.updatecheck {
+            result.updatecheck = Some(if updatecheck.updatedisabled != 0 {
+                UpdateCheckResult::none("noupdate", String::from("updates disabled by the client"))
+            } else {
+                update_check(&request, default_version, versions, session_manager)
+            });
+        }
+        result.ping = app.ping.is_some();
+        for event in &app.events {
+            record_event(&request, event, default_version, catalog, session_manager, mirror_state);
+        }
+        result.events = app.events.len();
+        apps.push(result);
+    }
+    apps
+}
+
+fn to_xml_app(app:AppResult) -> OmahaAppResponse {
+    let updatecheck = app.updatecheck.map(|updatecheck| {
+        if updatecheck.downloadlink.is_empty() {
+            return OmahaUpdateCheckResponse{ status:updatecheck.status, info:updatecheck.info, urls:None, manifest:None };
+        }
+        // Omaha v3 splits the download link into a codebase and a package name
+        let (codebase, name) = match updatecheck.downloadlink.rsplit_once('/') {
+            Some((codebase, name)) => (format!("{codebase}/"), name.to_string()),
+            None => (String::new(), updatecheck.downloadlink.clone())
+        };
+        OmahaUpdateCheckResponse{
+            status:updatecheck.status,
+            info:String::new(),
+            urls:Some(OmahaUrls{ url:vec![OmahaUrl{ codebase }] }),
+            manifest:Some(OmahaManifest{
+                version:updatecheck.version,
+                packages:OmahaPackages{ package:vec![OmahaPackage{ name, required:true, size:updatecheck.size, hash_sha256:updatecheck.sha256 }] }
+            })
+        }
+    });
+    OmahaAppResponse{
+        appid:app.appid,
+        status:app.status,
+        updatecheck,
+        ping:if app.ping { Some(OmahaAck{ status:"ok" }) } else { None },
+        events:(0..app.events).map(|_| OmahaAck{ status:"ok" }).collect(),
+    }
+}
+
+fn xml_response(status_code:i32, body:&str, cup:Option<&CupRequest>) -> Response {
+    with_proof(create_response_with_type(status_code, "application/xml", body), cup)
+}
+
+// Adds the CUP server proof when the client asked for one
+pub fn with_proof(response:Response, cup:Option<&CupRequest>) -> Response {
+    match cup {
+        Some(cup) => {
+            let proof = cup::proof(cup, response.bytes());
+            with_header(response, cup::PROOF_HEADER, &proof)
+        },
+        None => response
+    }
+}
+
+fn write_response(response:&OmahaResponse) -> String {
+    let body = quick_xml::se::to_string(response).unwrap();
+    format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>{body}")
+}
+
+// POST /service/update2
+pub fn handle_update2(request:&HttpRequest, default_version:&Version, products:&Products, session_manager:&mut SessionManager, mirror_state:&mut MirrorState, cup_key:Option<&CupKey>, nonces:&mut NonceCache) -> Response {
+    let cup = match cup::check_request(&request.query, &request.body, cup_key, nonces) {
+        Ok(cup) => cup,
+        Err(response) => return response
+    };
+
+    let omaha_request = match quick_xml::de::from_str::<OmahaRequest>(&request.body) {
+        Ok(omaha_request) => omaha_request,
+        Err(err) => {
+            println!("Invalid Omaha request: {}", err);
+            return xml_response(400, "", cup.as_ref());
+        }
+    };
+
+    let protocol_ok = omaha_request.protocol == OMAHA_PROTOCOL;
+    let apps = answer(&omaha_request, protocol_ok, "xml", default_version, products, session_manager, mirror_state);
+    let (elapsed_seconds, timer) = daystart();
+    let response = OmahaResponse{
+        protocol:OMAHA_PROTOCOL,
+        server:"hypertrail",
+        daystart:OmahaDayStart{ elapsed_seconds, elapsed_days:timer.elapsed_days },
+        apps:apps.into_iter().map(to_xml_app).collect(),
+    };
+    xml_response(200, &write_response(&response), cup.as_ref())
+}
@@ -0,0 +1,158 @@
+use serde::{Serialize, Deserialize};
+use crate::cup;
+use crate::omaha::{answer, daystart, with_proof, AppResult, OmahaRequest};
+use crate::cup::{CupKey, CupRequest, NonceCache};
+use crate::session::SessionManager;
+use crate::mirror::MirrorState;
+use crate::version::Version;
+use crate::catalog::Products;
+use crate::http::{create_response_with_type, HttpRequest, Response};
+
+// Omaha 4 JSON protocol, spoken by newer Chromium updater builds. Requests are translated
+// through the same path as Omaha v3 XML, responses are prefixed with the safe JSON prefix
+// that Chromium strips before parsing.
+
+const OMAHA_JSON_PROTOCOL:&str = "4.0";
+const SAFE_JSON_PREFIX:&str = ")]}'\n";
+
+#[derive(Deserialize)]
+struct OmahaJsonRequest{
+    request:OmahaRequest,
+}
+
+#[derive(Serialize)]
+struct OmahaJsonResponse{
+    response:OmahaJsonBody,
+}
+
+#[derive(Serialize)]
+struct OmahaJsonBody{
+    protocol:&'static str,
+    server:&'static str,
+    daystart:OmahaJsonDayStart,
+    apps:Vec<OmahaJsonApp>,
+}
+
+#[derive(Serialize)]
+struct OmahaJsonDayStart{
+    elapsed_days:i32,
+    elapsed_seconds:u64,
+}
+
+#[derive(Serialize)]
+struct OmahaJsonApp{
+    appid:String,
+    status:&'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updatecheck:Option<OmahaJsonUpdateCheck>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ping:Option<OmahaJsonAck>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    events:Vec<OmahaJsonAck>,
+}
+
+#[derive(Serialize)]
+struct OmahaJsonUpdateCheck{
+    status:&'static str,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    info:String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    nextversion:String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pipelines:Vec<OmahaJsonPipeline>,
+}
+
+// A pipeline is a list of operations the client runs in order, we only hand out full downloads
+#[derive(Serialize)]
+struct OmahaJsonPipeline{
+    pipeline_id:&'static str,
+    operations:Vec<OmahaJsonOperation>,
+}
+
+#[derive(Serialize)]
+struct OmahaJsonOperation{
+    #[serde(rename = "type")]
+    operation_type:&'static str,
+    urls:Vec<OmahaJsonUrl>,
+    #[serde(skip_serializing_if = "crate::is_zero")]
+    size:u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    out:Option<OmahaJsonHash>, // what the downloaded file must hash to
+}
+
+#[derive(Serialize)]
+struct OmahaJsonHash{
+    sha256:String,
+}
+
+#[derive(Serialize)]
+struct OmahaJsonUrl{
+    url:String,
+}
+
+#[derive(Serialize)]
+struct OmahaJsonAck{
+    status:&'static str,
+}
+
+fn to_json_app(app:AppResult) -> OmahaJsonApp {
+    let updatecheck = app.updatecheck.map(|updatecheck| {
+        let pipelines = if updatecheck.downloadlink.is_empty() {
+            vec![]
+        } else {
+            vec![OmahaJsonPipeline{
+                pipeline_id:"full",
+                operations:vec![OmahaJsonOperation{
+                    operation_type:"download",
+                    urls:vec![OmahaJsonUrl{ url:updatecheck.downloadlink }],
+                    size:updatecheck.size,
+                    out:if updatecheck.sha256.is_empty() { None } else { Some(OmahaJsonHash{ sha256:updatecheck.sha256 }) }
+                }]
+            }]
+        };
+        OmahaJsonUpdateCheck{ status:updatecheck.status, info:updatecheck.info, nextversion:updatecheck.version, pipelines }
+    });
+    OmahaJsonApp{
+        appid:app.appid,
+        status:app.status,
+        updatecheck,
+        ping:if app.ping { Some(OmahaJsonAck{ status:"ok" }) } else { None },
+        events:(0..app.events).map(|_| OmahaJsonAck{ status:"ok" }).collect(),
+    }
+}
+
+fn json_response(status_code:i32, body:&str, cup:Option<&CupRequest>) -> Response {
+    with_proof(create_response_with_type(status_code, "application/json", body), cup)
+}
+
+// POST /service/update2/json
+pub fn handle_update2_json(request:&HttpRequest, default_version:&Version, products:&Products, session_manager:&mut SessionManager, mirror_state:&mut MirrorState, cup_key:Option<&CupKey>, nonces:&mut NonceCache) -> Response {
+    let cup = match cup::check_request(&request.query, &request.body, cup_key, nonces) {
+        Ok(cup) => cup,
+        Err(response) => return response
+    };
+
+    // clients don't prefix requests, but accept it in case a proxy echoes the response format
+    let body = request.body.strip_prefix(SAFE_JSON_PREFIX).unwrap_or(&request.body);
+    let omaha_request = match serde_json::from_str::<OmahaJsonRequest>(body) {
+        Ok(omaha_request) => omaha_request.request,
+        Err(err) => {
+            println!("Invalid Omaha JSON request: {}", err);
+            return json_response(400, "", cup.as_ref());
+        }
+    };
+
+    let protocol_ok = omaha_request.protocol == OMAHA_JSON_PROTOCOL;
+    let apps = answer(&omaha_request, protocol_ok, "json", default_version, products, session_manager, mirror_state);
+    let (elapsed_seconds, timer) = daystart();
+    let response = OmahaJsonResponse{
+        response:OmahaJsonBody{
+            protocol:OMAHA_JSON_PROTOCOL,
+            server:"hypertrail",
+            daystart:OmahaJsonDayStart{ elapsed_days:timer.elapsed_days, elapsed_seconds },
+            apps:apps.into_iter().map(to_json_app).collect(),
+        }
+    };
+    let body = format!("{SAFE_JSON_PREFIX}{}", serde_json::to_string(&response).unwrap());
+    json_response(200, &body, cup.as_ref())
+}
@@ -0,0 +1,177 @@
+use std::cmp::Ordering;
+use crate::{Architecture, OperatingSystem, Platform, SysRequirements};
+
+// Matches the client's OS against the per-platform builds of a catalog entry. Entries that
+// don't list any builds run everywhere. An empty OS version or service pack from the
+// client is treated as supported, like unknown hardware values.
+
+fn parse_platform(platform:&str) -> Platform {
+    match platform.trim().to_ascii_lowercase().as_str() {
+        "linux" => Platform::Linux,
+        "macos" | "mac" | "osx" => Platform::MacOS,
+        "windows" | "win" => Platform::Windows,
+        _ => Platform::Unknown
+    }
+}
+
+fn parse_arch(arch:&str) -> Option<Architecture> {
+    match arch.trim().to_ascii_lowercase().as_str() {
+        "arm" => Some(Architecture::Arm),
+        "arm64" | "aarch64" => Some(Architecture::Arm64),
+        "x86" | "i386" | "i686" => Some(Architecture::x86),
+        "x86_64" | "amd64" => Some(Architecture::x86_64),
+        "x64" => Some(Architecture::x64),
+        _ => None
+    }
+}
+
+pub fn platform(os:&OperatingSystem) -> Platform {
+    parse_platform(&os.platform)
+}
+
+pub fn arch(os:&OperatingSystem) -> Option<Architecture> {
+    parse_arch(&os.arch)
+}
+
+// x86_64 and x64 name the same architecture
+pub fn same_arch(a:&Architecture, b:&Architecture) -> bool {
+    let canonical = |arch:&Architecture| match arch {
+        Architecture::x64 => Architecture::x86_64,
+        other => other.clone()
+    };
+    canonical(a) == canonical(b)
+}
+
+// Compares dotted OS versions ("10.0.19045", "14.2") component by component
+fn compare_versions(a:&str, b:&str) -> Ordering {
+    let parts = |version:&str| version
+        .split('.')
+        .map(|part| part.trim().parse::<u64>().unwrap_or(0))
+        .collect::<Vec<u64>>();
+    let (a, b) = (parts(a), parts(b));
+    for index in 0..a.len().max(b.len()) {
+        let ordering = a.get(index).unwrap_or(&0).cmp(b.get(index).unwrap_or(&0));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+// "SP2", "Service Pack 2" and "2" all report service pack 2
+fn service_pack(sp:&str) -> Option<i32> {
+    let digits = sp.chars().filter(|c| c.is_ascii_digit()).collect::<String>();
+    digits.parse::<i32>().ok()
+}
+
+// Returns the build the client should download, None when the entry has no per-platform builds.
+// The error explains why the client's OS can't run the entry.
+pub fn find_build<'a>(os:&OperatingSystem, builds:&'a [SysRequirements]) -> Result<Option<&'a SysRequirements>, String> {
+    if builds.is_empty() {
+        return Ok(None);
+    }
+
+    let platform = parse_platform(&os.platform);
+    let arch = parse_arch(&os.arch);
+    let matching = builds
+        .iter()
+        .filter(|build| build.platform == platform && arch.as_ref().is_some_and(|arch| same_arch(&build.arch, arch)))
+        .collect::<Vec<&SysRequirements>>();
+    if matching.is_empty() {
+        return Err(format!("has no build for {} {}", os.platform, os.arch));
+    }
+
+    let supported = matching.iter().find(|build| {
+        let version_ok = os.version.is_empty() || compare_versions(&os.version, &build.min_os_version) != Ordering::Less;
+        let sp_ok = service_pack(&os.sp).is_none_or(|sp| sp >= build.min_sp);
+        version_ok && sp_ok
+    });
+    match supported {
+        Some(build) => Ok(Some(build)),
+        None => {
+            let build = matching[0];
+            let mut requirement = format!("requires {} {}", os.platform, build.min_os_version);
+            if build.min_sp > 0 {
+                requirement = format!("{requirement} service pack {}", build.min_sp);
+            }
+            Err(requirement)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn os(platform:&str, arch:&str, version:&str, sp:&str) -> OperatingSystem {
+        OperatingSystem{
+            platform:String::from(platform),
+            sp:String::from(sp),
+            arch:String::from(arch),
+            dedup:String::new(),
+            version:String::from(version)
+        }
+    }
+
+    fn build(platform:Platform, arch:Architecture, min_os_version:&str, min_sp:i32) -> SysRequirements {
+        SysRequirements{
+            platform,
+            arch,
+            min_os_version:String::from(min_os_version),
+            min_sp,
+            server:format!("https://example.com/{min_os_version}"),
+            sha256:String::new(),
+            size:0
+        }
+    }
+
+    #[test]
+    fn dotted_versions() {
+        assert_eq!(compare_versions("10.0.19045", "10.0"), Ordering::Greater);
+        assert_eq!(compare_versions("10.0.0", "10.0"), Ordering::Equal);
+        assert_eq!(compare_versions("14.2", "14.10"), Ordering::Less);
+        assert_eq!(compare_versions("6.1", "10.0"), Ordering::Less);
+        assert_eq!(compare_versions("10", "10.0.1"), Ordering::Less);
+    }
+
+    #[test]
+    fn service_packs() {
+        assert_eq!(service_pack("SP2"), Some(2));
+        assert_eq!(service_pack("Service Pack 2"), Some(2));
+        assert_eq!(service_pack("2"), Some(2));
+        assert_eq!(service_pack(""), None);
+    }
+
+    #[test]
+    fn builds_for_the_client_os() {
+        let builds = [
+            build(Platform::Windows, Architecture::x64, "10.0", 0),
+            build(Platform::MacOS, Architecture::Arm64, "14.2", 0)
+        ];
+        let found = |os:&OperatingSystem| find_build(os, &builds).map(|build| build.map(|build| build.server.as_str()));
+
+        assert_eq!(found(&os("Windows", "x86_64", "10.0.19045", "")), Ok(Some("https://example.com/10.0")));
+        assert_eq!(found(&os("mac", "aarch64", "14.10", "")), Ok(Some("https://example.com/14.2")));
+        assert_eq!(found(&os("mac", "aarch64", "13.6", "")), Err(String::from("requires mac 14.2")));
+        // an unknown OS version is supported
+        assert_eq!(found(&os("Windows", "x64", "", "")), Ok(Some("https://example.com/10.0")));
+
+        assert_eq!(found(&os("Linux", "x86_64", "6.1", "")), Err(String::from("has no build for Linux x86_64")));
+        assert_eq!(found(&os("Windows", "arm64", "10.0", "")), Err(String::from("has no build for Windows arm64")));
+        assert_eq!(found(&os("Windows", "sparc", "10.0", "")), Err(String::from("has no build for Windows sparc")));
+
+        // entries without builds run everywhere
+        assert!(matches!(find_build(&os("Linux", "x86_64", "6.1", ""), &[]), Ok(None)));
+    }
+
+    #[test]
+    fn minimum_service_pack() {
+        let builds = [build(Platform::Windows, Architecture::x86, "6.1", 1)];
+        let found = |sp:&str| find_build(&os("Windows", "x86", "6.1", sp), &builds).map(|build| build.is_some());
+
+        assert_eq!(found("Service Pack 1"), Ok(true));
+        assert_eq!(found("SP2"), Ok(true));
+        assert_eq!(found(""), Ok(true));
+        assert_eq!(found("SP0"), Err(String::from("requires Windows 6.1 service pack 1")));
+    }
+}
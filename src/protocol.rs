@@ -0,0 +1,17 @@
+// Versions of the update protocol this server speaks. Clients newer than MAX_PROTOCOL are
+// answered with MAX_PROTOCOL, clients older than MIN_PROTOCOL are rejected with
+// errorunsupportedprotocol.
+pub const MIN_PROTOCOL:f32 = 1.0;
+pub const MAX_PROTOCOL:f32 = 1.0;
+
+pub fn negotiate(client_protocol:f32) -> Result<f32, String> {
+    if !client_protocol.is_finite() || client_protocol < MIN_PROTOCOL {
+        return Err(format!("protocol {:?} is not supported, this server speaks {:?} to {:?}", client_protocol, MIN_PROTOCOL, MAX_PROTOCOL));
+    }
+    Ok(client_protocol.min(MAX_PROTOCOL))
+}
+
+// Protocol version echoed back in responses
+pub fn negotiated(client_protocol:f32) -> f32 {
+    negotiate(client_protocol).unwrap_or(MAX_PROTOCOL)
+}
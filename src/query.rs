@@ -0,0 +1,98 @@
+use serde_json::Value;
+
+// Query parameters for simple update checks: GET /latest?updater=hypertrail&channel=Stable&os.platform=linux
+// Each parameter overrides the field of the same name in the default request, nested fields
+// are reached with dots. Numbers and booleans are parsed as the field they replace, unknown
+// parameters (cache busters and the like) are ignored.
+
+fn hex_value(digit:u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+pub fn percent_decode(value:&str) -> String {
+    decode(value, true)
+}
+
+// Paths keep '+' as it is, only query strings use it for a space
+pub fn percent_decode_path(path:&str) -> String {
+    decode(path, false)
+}
+
+fn decode(value:&str, plus_is_space:bool) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_is_space => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                    (Some(high), Some(low)) => {
+                        decoded.push(high << 4 | low);
+                        i += 2;
+                    },
+                    _ => decoded.push(b'%')
+                }
+            },
+            byte => decoded.push(byte)
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+pub fn parse_query(query:&str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+// The field a dotted key points at, None when the request has no such field
+fn field<'a>(value:&'a mut Value, key:&str) -> Option<&'a mut Value> {
+    key.split('.').try_fold(value, |value, name| value.as_object_mut()?.get_mut(name))
+}
+
+fn typed(current:&Value, key:&str, value:&str) -> Result<Value, String> {
+    let invalid = || format!("invalid value {value} for {key}");
+    match current {
+        Value::Bool(_) => match value {
+            "1" | "true" => Ok(Value::Bool(true)),
+            "0" | "false" => Ok(Value::Bool(false)),
+            _ => Err(invalid())
+        },
+        Value::Number(_) => {
+            let number = value.parse::<f64>().map_err(|_| invalid())?;
+            if number.fract() == 0.0 && current.is_i64() {
+                Ok(Value::from(number as i64))
+            } else {
+                serde_json::Number::from_f64(number).map(Value::Number).ok_or_else(invalid)
+            }
+        },
+        Value::String(_) => Ok(Value::String(String::from(value))),
+        _ => Err(format!("{key} can't be set from the query"))
+    }
+}
+
+// Writes the query parameters into the JSON form of a default request. fallback names a nested
+// object that also receives parameters the top level doesn't have, so a status report can say
+// sessionid=... instead of request.sessionid=...
+pub fn apply_query(request:&mut Value, query:&str, fallback:Option<&str>) -> Result<(), String> {
+    for (key, value) in parse_query(query) {
+        let fallback_key = fallback.map(|fallback| format!("{fallback}.{key}"));
+        let target = if field(request, &key).is_some() {
+            field(request, &key)
+        } else {
+            fallback_key.as_deref().and_then(|fallback_key| field(request, fallback_key))
+        };
+        let Some(target) = target else {
+            continue;
+        };
+        *target = typed(target, &key, &value)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,113 @@
+use crate::Request;
+use crate::version::Version;
+
+// Staged rollouts: every client falls into a stable bucket (0-99) for each version and
+// a version is only offered to the clients whose bucket is below its rollout percentage.
+// Raising the percentage keeps everyone already offered the version in the rollout,
+// lowering it stops new offers for the buckets above the new value.
+
+const FNV_OFFSET_BASIS:u64 = 0xcbf29ce484222325;
+const FNV_PRIME:u64 = 0x100000001b3;
+
+// FNV-1a, unlike the std hasher its output never changes between builds of the server
+fn fnv1a(bytes:&[u8]) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+// The install id identifies a single install, fall back to the dedup id when the client doesn't send one
+pub fn client_key(request:&Request) -> Option<&str> {
+    if !request.installid.is_empty() {
+        return Some(&request.installid);
+    }
+    if !request.os.dedup.is_empty() {
+        return Some(&request.os.dedup);
+    }
+    None
+}
+
+pub fn bucket(key:&str, version:&Version) -> u32 {
+    let seed = format!("{}:{}", version.number(), key);
+    (fnv1a(seed.as_bytes()) % 100) as u32
+}
+
+pub fn is_offered(version:&Version, request:&Request) -> bool {
+    if version.rollout >= 100 {
+        return true;
+    }
+    match client_key(request) {
+        Some(key) => bucket(key, version) < version.rollout,
+        None => false // without a stable id we can't keep the client in the same bucket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{default_request, default_version};
+
+    fn version(rollout:u32) -> Version {
+        let mut version = default_version();
+        (version.major, version.minor, version.build) = (0, 3, 1);
+        version.rollout = rollout;
+        version
+    }
+
+    fn client(installid:&str) -> Request {
+        let mut request = default_request();
+        request.installid = String::from(installid);
+        request
+    }
+
+    #[test]
+    fn bucket_is_stable() {
+        let version = version(50);
+        // pinned, a different value means every client moved to another bucket
+        assert_eq!(bucket("install-1", &version), 44);
+        assert_eq!(bucket("install-1", &version), bucket("install-1", &version));
+        assert!((0..100).all(|index| bucket(&format!("install-{index}"), &version) < 100));
+    }
+
+    #[test]
+    fn buckets_are_evenly_distributed() {
+        let version = version(50);
+        let mut counts = [0u32; 100];
+        for index in 0..100_000 {
+            counts[bucket(&format!("install-{index}"), &version) as usize] += 1;
+        }
+        // 1000 per bucket on average
+        assert!(counts.iter().all(|count| (850..1150).contains(count)), "{counts:?}");
+    }
+
+    #[test]
+    fn zero_and_full_rollout() {
+        for index in 0..100 {
+            let request = client(&format!("install-{index}"));
+            assert!(!is_offered(&version(0), &request));
+            assert!(is_offered(&version(100), &request));
+        }
+        // a full rollout doesn't need a stable id
+        assert!(is_offered(&version(100), &default_request()));
+        assert!(!is_offered(&version(99), &default_request()));
+    }
+
+    #[test]
+    fn rollout_boundary() {
+        let request = client("install-1");
+        let bucket = bucket("install-1", &version(0));
+        assert!(!is_offered(&version(bucket), &request));
+        assert!(is_offered(&version(bucket + 1), &request));
+    }
+
+    #[test]
+    fn dedup_id_stands_in_for_the_install_id() {
+        let mut request = default_request();
+        request.os.dedup = String::from("install-1");
+        assert_eq!(client_key(&request), Some("install-1"));
+        assert_eq!(is_offered(&version(50), &request), is_offered(&version(50), &client("install-1")));
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::MutexGuard;
+use crate::ServerState;
+use crate::http::{create_response_with_type, with_header, HttpRequest, Response};
+
+// Maps method and path to the handler of an endpoint. Requests for unknown paths get a 404
+// (with 404.html), a method the route doesn't list gets a 405 with an Allow header, so
+// handlers only see requests they take. Static pages come from pages.json,
+// {"/": "index.html", "/about": "about.html"}, without the file / and /index.html serve index.html.
+pub const PAGES_PATH:&str = "pages.json";
+const NOT_FOUND_PAGE:&str = "404.html";
+const INDEX_PAGE:&str = "index.html";
+const PAGE_METHODS:&[&str] = &["GET", "HEAD"];
+
+// An endpoint. Handlers get the parsed request and the server state and return the response,
+// the connection writes it (only the head for HEAD requests). Connections are served on their
+// own threads, so handlers are shared between them.
+pub trait Handler: Send + Sync{
+    fn handle(&self, request:&HttpRequest, state:&mut ServerState) -> Response;
+}
+
+impl<F> Handler for F where F: Fn(&HttpRequest, &mut ServerState) -> Response + Send + Sync {
+    fn handle(&self, request:&HttpRequest, state:&mut ServerState) -> Response {
+        self(request, state)
+    }
+}
+
+// Endpoints that only read files, e.g the artifact store, don't need the server state and are
+// answered without taking its lock, a slow disk doesn't hold up the update checks
+type FileHandler = Box<dyn Fn(&HttpRequest) -> Response + Send + Sync>;
+
+enum Endpoint{
+    State(Box<dyn Handler>),
+    Files(FileHandler),
+}
+
+struct Route{
+    path:&'static str,
+    prefix:bool, // the path is a prefix, e.g /artifacts/<file>
+    methods:&'static [&'static str],
+    endpoint:Endpoint,
+}
+
+#[derive(Default)]
+pub struct Router{
+    routes:Vec<Route>,
+    pages:HashMap<String, String>, // path => file of a static page
+}
+
+impl Router{
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    pub fn route(mut self, methods:&'static [&'static str], path:&'static str, handler:impl Handler + 'static) -> Router {
+        self.routes.push(Route{ path, prefix:false, methods, endpoint:Endpoint::State(Box::new(handler)) });
+        self
+    }
+
+    // Every path below the prefix goes to the handler, it serves files and gets no state
+    pub fn files(mut self, methods:&'static [&'static str], path:&'static str, handler:impl Fn(&HttpRequest) -> Response + Send + Sync + 'static) -> Router {
+        self.routes.push(Route{ path, prefix:true, methods, endpoint:Endpoint::Files(Box::new(handler)) });
+        self
+    }
+
+    pub fn pages(mut self, pages:HashMap<String, String>) -> Router {
+        self.pages = pages;
+        self
+    }
+
+    // lock_state is only called for endpoints that need the state
+    pub fn dispatch<'a>(&self, request:&HttpRequest, lock_state:impl FnOnce() -> MutexGuard<'a, ServerState>) -> Response {
+        let method = request.method.as_str();
+        let route = self.routes.iter().find(|route| if route.prefix { request.path.starts_with(route.path) } else { request.path == route.path });
+        let methods = match (route, self.pages.get(&request.path)) {
+            (Some(route), _) => route.methods,
+            (None, Some(_)) => PAGE_METHODS,
+            (None, None) => {
+                println!("No route for {} {}", method, request.path);
+                return not_found();
+            }
+        };
+        if !methods.contains(&method) {
+            println!("{} is not allowed on {}", method, request.path);
+            return with_header(create_response_with_type(405, "text/plain", "method not allowed"), "Allow", &methods.join(", "));
+        }
+
+        println!("Incoming {} request", method);
+        match route.map(|route| &route.endpoint) {
+            Some(Endpoint::State(handler)) => handler.handle(request, &mut lock_state()),
+            Some(Endpoint::Files(handler)) => handler(request),
+            None => page(&self.pages[&request.path])
+        }
+    }
+}
+
+pub fn load_pages(path:&str) -> Result<HashMap<String, String>, String> {
+    let pages = match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str::<HashMap<String, String>>(&contents).map_err(|err| format!("invalid {path}: {err}"))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::from([
+            (String::from("/"), String::from(INDEX_PAGE)),
+            (format!("/{INDEX_PAGE}"), String::from(INDEX_PAGE)),
+        ]),
+        Err(err) => return Err(format!("failed to read {path}: {err}"))
+    };
+    for (page, file) in &pages {
+        println!("Page {} : {}", page, file);
+    }
+    Ok(pages)
+}
+
+pub fn not_found() -> Response {
+    match fs::read_to_string(NOT_FOUND_PAGE) {
+        Ok(page) => create_response_with_type(404, "text/html; charset=utf-8", &page),
+        Err(_) => create_response_with_type(404, "text/plain", "not found")
+    }
+}
+
+fn page(file:&str) -> Response {
+    match fs::read_to_string(file) {
+        Ok(page) => create_response_with_type(200, "text/html; charset=utf-8", &page),
+        Err(err) => {
+            println!("Failed to read page {}: {}", file, err);
+            not_found()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_served_without_the_state() {
+        let router = Router::new().files(&["GET"], "/files/", |_request:&HttpRequest| create_response_with_type(200, "text/plain", "file"));
+        let request = HttpRequest::new("GET /files/a HTTP/1.1", String::new(), String::new()).unwrap();
+        let response = router.dispatch(&request, || panic!("the state was locked"));
+        assert_eq!(response.status_code, 200);
+
+        // the method check comes first all the same
+        let request = HttpRequest::new("POST /files/a HTTP/1.1", String::new(), String::new()).unwrap();
+        assert_eq!(router.dispatch(&request, || panic!("the state was locked")).status_code, 405);
+    }
+}
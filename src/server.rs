@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use ed25519_dalek::SigningKey;
+use crate::{artifacts, compression, connection, endpoints, session};
+use crate::catalog::Products;
+use crate::connection::{Connection, KeepAlive, RequestReader};
+use crate::cup::{CupKey, NonceCache};
+use crate::http::{self, create_response_with_type, header_value, Head, HttpRequest, RequestBody, Response, MAX_BODY_SIZE, MAX_HEADERS, MAX_HEAD_SIZE};
+use crate::mirror::MirrorState;
+use crate::router::Router;
+use crate::session::SessionManager;
+
+// What a server is started with. main reads it from the files and environment variables,
+// the default serves no pages, signs nothing, rejects CUP, compresses from 1 KiB and serves
+// artifacts from ./artifacts.
+pub struct ServerConfig{
+    pub pages:HashMap<String, String>, // page path -> file, see router::load_pages
+    pub signing_key:Option<SigningKey>, // signs /latest, /download and /status responses
+    pub cup_key:Option<CupKey>, // answers CUP requests of the Omaha endpoints
+    pub compression_min_size:usize, // smallest body that is compressed, 0 turns compression off
+    pub admin_token:Option<String>, // token of the admin endpoints, they are disabled without one
+    pub artifacts_dir:PathBuf, // store served from /artifacts/
+}
+
+impl Default for ServerConfig{
+    fn default() -> ServerConfig {
+        ServerConfig{ pages:HashMap::new(), signing_key:None, cup_key:None, compression_min_size:compression::COMPRESSION_MIN_SIZE, admin_token:None, artifacts_dir:PathBuf::from(artifacts::ARTIFACTS_DIR) }
+    }
+}
+
+// Everything the endpoints share and change while the server runs
+pub struct ServerState{
+    pub products:Products,
+    pub session_manager:SessionManager,
+    pub mirror_state:MirrorState,
+    pub signing_key:Option<SigningKey>,
+    pub cup_key:Option<CupKey>,
+    pub nonces:NonceCache, // CUP nonces already used
+    pub admin_token:Option<String>,
+}
+
+// The update server without the network. handle answers a request in-process, which is all
+// that tools and tests need; serve_connection reads the requests of a client connection and
+// writes the answers back with the connection headers and the negotiated compression, and
+// serve runs serve_connection on a thread per client. Requests take turns on the state,
+// reading requests, writing responses and serving files happen outside the lock.
+pub struct Server{
+    router:Router,
+    compression_min_size:usize,
+    reload:Arc<AtomicBool>, // set by SIGHUP once reload_on_sighup registered it
+    pub state:Mutex<ServerState>,
+}
+
+// Counts a connection as active until its thread ends, panicking or not
+pub(crate) struct ActiveConnection(pub(crate) Arc<AtomicUsize>);
+
+impl Drop for ActiveConnection{
+    fn drop(&mut self){
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Server{
+    pub fn new(products:Products, config:ServerConfig) -> Server {
+        Server{
+            router:endpoints::routes(config.pages, config.artifacts_dir),
+            compression_min_size:config.compression_min_size,
+            reload:Arc::new(AtomicBool::new(false)),
+            state:Mutex::new(ServerState{
+                products,
+                session_manager:session::new_session_manager(),
+                mirror_state:MirrorState::default(),
+                signing_key:config.signing_key,
+                cup_key:config.cup_key,
+                nonces:NonceCache::default(),
+                admin_token:config.admin_token
+            })
+        }
+    }
+
+    // A handler that panicked only ends its own connection, the others keep using the state
+    pub fn lock_state(&self) -> MutexGuard<'_, ServerState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // SIGHUP reloads the catalogs before the next request, like POST /admin/reload
+    pub fn reload_on_sighup(&self) -> std::io::Result<()> {
+        #[cfg(unix)]
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&self.reload))?;
+        Ok(())
+    }
+
+    // HEAD requests get the whole GET response, the body is left out when it's written
+    pub fn handle(&self, request:&HttpRequest) -> Response {
+        self.router.dispatch(request, || {
+            let mut state = self.lock_state();
+            if self.reload.swap(false, Ordering::Relaxed) {
+                match state.products.reload() {
+                    Ok(_) => println!("Catalogs reloaded"),
+                    Err(err) => println!("Keeping the current catalogs, reload failed: {}", err)
+                }
+            }
+            state
+        })
+    }
+
+    // Accepts clients until the listener fails, accept turns a client into a Connection (e.g
+    // starts TLS). Clients beyond max_connections are closed right away.
+    pub fn serve(self:Arc<Server>, listener:TcpListener, mut accept:impl FnMut(TcpStream) -> Result<Connection, String>, keep_alive:KeepAlive, max_connections:usize){
+        let active = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    println!("Failed to accept connection: {}", err);
+                    continue;
+                }
+            };
+            if active.load(Ordering::SeqCst) >= max_connections {
+                println!("Closing connection, {} connections are open", max_connections);
+                continue;
+            }
+            let connection = match accept(stream) {
+                Ok(connection) => connection,
+                Err(err) => {
+                    println!("Failed to start connection: {}", err);
+                    continue;
+                }
+            };
+            active.fetch_add(1, Ordering::SeqCst);
+            let guard = ActiveConnection(Arc::clone(&active));
+            let server = Arc::clone(&self);
+            thread::spawn(move || {
+                let _guard = guard;
+                server.serve_connection(connection, &keep_alive);
+            });
+        }
+    }
+
+    // Serves the requests of one client until it closes the connection
+    pub fn serve_connection(&self, stream: Connection, keep_alive:&KeepAlive){
+        // one reader for the whole connection, it holds on to pipelined requests read ahead
+        let mut reader = BufReader::new(RequestReader::new(&stream, keep_alive.idle_timeout));
+
+        for served in 1..=keep_alive.max_requests {
+            // the request deadline starts with its first byte, until then only the idle timeout applies
+            reader.get_mut().wait();
+            let raw = match reader.fill_buf() {
+                Ok([]) => Ok(None),
+                Ok(_) => {
+                    reader.get_mut().start(keep_alive.request_timeout);
+                    http::read_request(&mut reader)
+                },
+                Err(err) => Err(err)
+            };
+            let raw = match raw {
+                Ok(Some(raw)) => raw,
+                Ok(None) => break,
+                Err(err) => {
+                    // an idle client timing out is the normal end of a persistent connection
+                    if served == 1 {
+                        println!("Failed to read request line: {}", err);
+                    }
+                    break;
+                }
+            };
+            let (request_line, headers) = (raw.request_line, raw.headers);
+
+            println!("Request Line: {}", request_line.trim());
+            let complete = raw.head == Head::Complete && matches!(raw.body, RequestBody::Complete(_));
+
+            let version = request_line.split_whitespace().nth(2).unwrap_or("HTTP/1.0");
+            let persistent = complete
+                && served < keep_alive.max_requests
+                && connection::wants_keep_alive(version, header_value(&headers, "Connection").as_deref());
+            let connection_headers = if !persistent {
+                vec![(String::from("Connection"), String::from("close"))]
+            } else {
+                let timeout = keep_alive.idle_timeout.as_secs();
+                let remaining = keep_alive.max_requests - served;
+                let mut connection_headers = vec![];
+                if version == "HTTP/1.0" {
+                    connection_headers.push((String::from("Connection"), String::from("keep-alive")));
+                }
+                connection_headers.push((String::from("Keep-Alive"), format!("timeout={timeout}, max={remaining}")));
+                connection_headers
+            };
+            let coding = compression::negotiate(header_value(&headers, "Accept-Encoding").as_deref());
+
+            let head_only = request_line.starts_with("HEAD ");
+            let mut response = match raw.body {
+                _ if raw.head == Head::TooLarge => {
+                    create_response_with_type(431, "text/plain", &format!("request head is over the limit of {MAX_HEAD_SIZE} bytes or {MAX_HEADERS} headers"))
+                },
+                // a head without its end isn't routed, the headers that are missing could change the answer
+                _ if raw.head == Head::TimedOut => create_response_with_type(408, "text/plain", "request head not received in time"),
+                _ if raw.head == Head::Truncated => create_response_with_type(400, "text/plain", "incomplete request head"),
+                RequestBody::Complete(body) => {
+                    println!("Body: {} bytes", body.len());
+                    match HttpRequest::new(&request_line, headers, body) {
+                        Some(request) => self.handle(&request),
+                        None => create_response_with_type(400, "text/plain", "malformed request line")
+                    }
+                },
+                // a cut off body would be read as an empty request
+                RequestBody::Truncated => create_response_with_type(400, "text/plain", "incomplete request body"),
+                RequestBody::TimedOut => create_response_with_type(408, "text/plain", "request body not received in time"),
+                RequestBody::TooLarge(length) => {
+                    create_response_with_type(413, "text/plain", &format!("request body of {length} bytes is over the limit of {MAX_BODY_SIZE}"))
+                },
+                RequestBody::InvalidLength => create_response_with_type(400, "text/plain", "invalid Content-Length"),
+                RequestBody::TransferEncoded => create_response_with_type(501, "text/plain", "Transfer-Encoding is not supported")
+            };
+            response = compression::compress(response, coding, self.compression_min_size);
+            response.headers.splice(0..0, connection_headers);
+
+            if let Err(err) = http::write_response(&stream, response, head_only) {
+                println!("Failed to send response: {}", err);
+                break;
+            }
+            println!("Response sent!");
+            if !persistent {
+                break;
+            }
+        }
+        stream.close();
+    }
+}
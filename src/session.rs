@@ -1,45 +1,108 @@
 use std::collections::HashMap;
-use crate::{Action, Platform, Request, Response};
-use crate::Action::latest;
+use std::mem;
+use std::time::{Duration, Instant};
+use crate::{Action, Request};
+
+const SESSION_TTL: Duration = Duration::from_secs(3600);
+const MAX_SESSIONS: usize = 100_000; // per set, a flood of new sessions rotates the sets early
 
 #[derive(Clone)]
 pub struct Session {
     pub requestid: String,
     pub possible_actions: Vec<Action>,
     pub previous_action: Action,
+    pub delta_failed: bool, // the client couldn't apply the delta patch, only offer the full package
+    pub mirrors: Vec<String>, // mirrors offered with the last download, the only ones the client can report on
 }
 
-pub struct Session_Manager {
-    pub sessions: HashMap<String, Session>,
+type SessionKey = (String, String);
+
+// Sessions are kept per (sessionid, appid): an Omaha request updates several apps under one
+// sessionid, /latest, /download and /status have an empty appid.
+// Like the CUP nonces, sessions are kept in a current and a previous set. The current set
+// becomes the previous one when it is SESSION_TTL old or full, and a session that is changed
+// moves back to the current set. A session that isn't used anymore is forgotten after one to
+// two periods (less only under a flood of MAX_SESSIONS new sessions), without scanning them all.
+pub struct SessionManager {
+    current: HashMap<SessionKey, Session>,
+    previous: HashMap<SessionKey, Session>,
+    rotated: Instant, // when current was started
 }
 
-pub fn new_session_manager() -> Session_Manager {
-    Session_Manager {
-        sessions: HashMap::new(),
+impl SessionManager {
+    fn rotate(&mut self, now: Instant) {
+        let age = now.duration_since(self.rotated);
+        if age >= SESSION_TTL * 2 {
+            self.previous.clear();
+            self.current.clear();
+            self.rotated = now;
+        } else if age >= SESSION_TTL || self.current.len() >= MAX_SESSIONS {
+            self.previous = mem::take(&mut self.current);
+            self.rotated = now;
+        }
+    }
+
+    fn contains(&self, key: &SessionKey) -> bool {
+        self.current.contains_key(key) || self.previous.contains_key(key)
+    }
+
+    fn insert(&mut self, key: SessionKey, session: Session, now: Instant) -> bool {
+        self.rotate(now);
+        if self.contains(&key) {
+            return false;
+        }
+        self.current.insert(key, session);
+        true
     }
+
+    fn get(&self, key: &SessionKey) -> Option<&Session> {
+        self.current.get(key).or_else(|| self.previous.get(key))
+    }
+
+    fn get_mut(&mut self, key: &SessionKey) -> Option<&mut Session> {
+        if let Some(session) = self.previous.remove(key) {
+            self.current.insert(key.clone(), session);
+        }
+        self.current.get_mut(key)
+    }
+
+    fn remove(&mut self, key: &SessionKey) -> bool {
+        self.current.remove(key).or_else(|| self.previous.remove(key)).is_some()
+    }
+}
+
+fn key(request: &Request) -> SessionKey {
+    (request.sessionid.clone(), request.appid.clone())
 }
 
-pub fn new_session(manager: &mut Session_Manager, request: &Request) -> bool {
-    if manager.sessions.contains_key(&request.sessionid) {
-        return false;
+pub fn new_session_manager() -> SessionManager {
+    SessionManager {
+        current: HashMap::new(),
+        previous: HashMap::new(),
+        rotated: Instant::now(),
     }
-    manager.sessions.insert(
-        request.sessionid.clone(),
+}
+
+pub fn new_session(manager: &mut SessionManager, request: &Request) -> bool {
+    manager.insert(
+        key(request),
         Session {
             requestid: request.requestid.clone(),
             possible_actions: vec![Action::latest],
             previous_action: Action::latest,
+            delta_failed: false,
+            mirrors: vec![],
         },
-    );
-    true
+        Instant::now(),
+    )
 }
 
 pub fn update_session_actions(
-    manager: &mut Session_Manager,
+    manager: &mut SessionManager,
     request: &Request,
     new_actions: Vec<Action>,
 ) -> (bool, String) {
-    if let Some(session) = manager.sessions.get_mut(&request.sessionid) {
+    if let Some(session) = manager.get_mut(&key(request)) {
         session.possible_actions = new_actions;
         return (true, String::from("success"));
     }
@@ -47,11 +110,11 @@ pub fn update_session_actions(
 }
 
 pub fn update_current_action(
-    manager: &mut Session_Manager,
+    manager: &mut SessionManager,
     request: &Request,
     new_action: Action,
 ) -> (bool, String) {
-    if let Some(session) = manager.sessions.get_mut(&request.sessionid) {
+    if let Some(session) = manager.get_mut(&key(request)) {
         session.previous_action = new_action;
         return (true, String::from("success"));
     }
@@ -59,17 +122,102 @@ pub fn update_current_action(
 }
 
 pub fn update_request(
-    manager: &mut Session_Manager,
+    manager: &mut SessionManager,
     request: &Request,
     new_requestid: String,
 ) -> (bool, String) {
-    if let Some(session) = manager.sessions.get_mut(&request.sessionid) {
+    if let Some(session) = manager.get_mut(&key(request)) {
         session.requestid = new_requestid.clone();
         return (true, String::from("success"));
     }
     (false, String::from("Invalid Session ID"))
 }
 
-pub fn remove_session(manager: &mut Session_Manager, sessionid: String) -> bool {
-    manager.sessions.remove(&sessionid).is_some()
+pub fn update_session_mirrors(
+    manager: &mut SessionManager,
+    request: &Request,
+    mirrors: Vec<String>,
+) -> bool {
+    if let Some(session) = manager.get_mut(&key(request)) {
+        session.mirrors = mirrors;
+        return true;
+    }
+    false
+}
+
+pub fn mark_delta_failed(
+    manager: &mut SessionManager,
+    request: &Request,
+) -> bool {
+    if let Some(session) = manager.get_mut(&key(request)) {
+        session.delta_failed = true;
+        return true;
+    }
+    false
+}
+
+pub fn get_session<'a>(manager: &'a SessionManager, request: &Request) -> Option<&'a Session> {
+    manager.get(&key(request))
+}
+
+pub fn remove_session(manager: &mut SessionManager, request: &Request) -> bool {
+    manager.remove(&key(request))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::default_request;
+
+    fn request(sessionid: &str) -> Request {
+        let mut request = default_request();
+        request.sessionid = String::from(sessionid);
+        request
+    }
+
+    fn session() -> Session {
+        Session {
+            requestid: String::new(),
+            possible_actions: vec![Action::latest],
+            previous_action: Action::latest,
+            delta_failed: false,
+            mirrors: vec![],
+        }
+    }
+
+    #[test]
+    fn unused_sessions_expire() {
+        let start = Instant::now();
+        let mut manager = SessionManager { rotated: start, ..new_session_manager() };
+        assert!(manager.insert(key(&request("a")), session(), start));
+        assert!(manager.insert(key(&request("b")), session(), start));
+        assert!(!manager.insert(key(&request("a")), session(), start + SESSION_TTL / 2));
+
+        // rotated into the previous set, still known; a is used and moves back to the current set
+        assert!(manager.insert(key(&request("c")), session(), start + SESSION_TTL + SESSION_TTL / 2));
+        assert!(get_session(&manager, &request("b")).is_some());
+        assert!(update_current_action(&mut manager, &request("a"), Action::download).0);
+
+        // b wasn't used for two periods
+        assert!(manager.insert(key(&request("d")), session(), start + SESSION_TTL * 2 + SESSION_TTL / 2));
+        assert!(get_session(&manager, &request("b")).is_none());
+        assert!(get_session(&manager, &request("a")).is_some_and(|session| session.previous_action == Action::download));
+
+        assert!(manager.insert(key(&request("e")), session(), start + SESSION_TTL * 6));
+        assert!(get_session(&manager, &request("a")).is_none());
+        assert!(remove_session(&mut manager, &request("e")));
+        assert!(!remove_session(&mut manager, &request("e")));
+    }
+
+    #[test]
+    fn sessions_are_bounded() {
+        let now = Instant::now();
+        let mut manager = new_session_manager();
+        for index in 0..MAX_SESSIONS * 3 {
+            assert!(manager.insert(key(&request(&index.to_string())), session(), now));
+        }
+        assert!(manager.current.len() <= MAX_SESSIONS && manager.previous.len() <= MAX_SESSIONS);
+        assert!(get_session(&manager, &request(&(MAX_SESSIONS * 3 - 1).to_string())).is_some());
+        assert!(get_session(&manager, &request("0")).is_none());
+    }
 }
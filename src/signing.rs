@@ -0,0 +1,122 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ed25519_dalek::{Signer, SigningKey};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::http::{create_response, with_header, Response};
+
+// Ed25519 signatures over the body of every update response (latest, download, status), sent
+// in the X-Signature header as "keyid=<id>; ed25519=<base64 signature>". Clients pin the public
+// key published at /publickey and drop responses whose signature doesn't verify, whatever
+// proxy or mirror they came through. The key is a hex encoded 32 byte seed, created on the
+// first start when the file doesn't exist. Without a key responses go out unsigned.
+pub const SIGNATURE_HEADER:&str = "X-Signature";
+const SIGNING_KEY_VAR:&str = "UPDATESERVER_SIGNING_KEY";
+const SIGNING_KEY_PATH:&str = "signing.key";
+
+#[derive(Serialize, Deserialize)]
+struct PublicKeyResponse{
+    algorithm:String,
+    keyid:String, // first 8 bytes of the sha256 of the public key, hex
+    publickey:String, // raw 32 byte public key, base64
+}
+
+pub fn key_path() -> String {
+    match env::var(SIGNING_KEY_VAR) {
+        Ok(path) if !path.is_empty() => path,
+        _ => String::from(SIGNING_KEY_PATH)
+    }
+}
+
+// Private keys are readable by the owner only from the moment the file exists, and a file that
+// appeared in the meantime is never overwritten
+pub fn create_key_file(path:&str, contents:&str) -> Result<(), String> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(|err| format!("failed to create {path}: {err}"))?;
+    file.write_all(contents.as_bytes()).map_err(|err| format!("failed to write {path}: {err}"))
+}
+
+fn generate_key(path:&str) -> Result<SigningKey, String> {
+    let mut seed = [0u8; 32];
+    getrandom::getrandom(&mut seed).map_err(|err| format!("failed to generate a signing key: {err}"))?;
+    create_key_file(path, &hex::encode(seed))?;
+    println!("Created a new signing key in {}", path);
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+fn read_key(path:&str) -> Result<SigningKey, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return generate_key(path),
+        Err(err) => return Err(format!("failed to read {path}: {err}"))
+    };
+    let seed = hex::decode(contents.trim()).map_err(|err| format!("invalid signing key in {path}: {err}"))?;
+    let seed:[u8; 32] = seed.try_into().map_err(|_| format!("signing key in {path} must be 32 bytes"))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+pub fn load_key(path:&str) -> Result<SigningKey, String> {
+    let key = read_key(path)?;
+    println!("Signing responses with key {}", keyid(&key));
+    Ok(key)
+}
+
+fn keyid(key:&SigningKey) -> String {
+    hex::encode(&Sha256::digest(key.verifying_key().as_bytes())[..8])
+}
+
+// Adds the signature of the response body, the response stays unsigned without a key
+pub fn sign(response:Response, key:Option<&SigningKey>) -> Response {
+    let Some(key) = key else {
+        return response;
+    };
+    let signature = format!("keyid={}; ed25519={}", keyid(key), BASE64.encode(key.sign(response.bytes()).to_bytes()));
+    with_header(response, SIGNATURE_HEADER, &signature)
+}
+
+// GET /publickey
+pub fn handle_public_key(key:Option<&SigningKey>) -> Response {
+    match key {
+        Some(key) => {
+            let response_object = PublicKeyResponse{
+                algorithm:String::from("ed25519"),
+                keyid:keyid(key),
+                publickey:BASE64.encode(key.verifying_key().as_bytes())
+            };
+            create_response(200, &serde_json::to_string(&response_object).unwrap())
+        },
+        None => create_response(404, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_keys_are_private_and_never_overwritten() {
+        let path = std::env::temp_dir().join(format!("updateserver-signing-{}.key", std::process::id()));
+        let path = path.to_string_lossy();
+        let _ = fs::remove_file(&*path);
+
+        let key = load_key(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&*path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        assert_eq!(load_key(&path).unwrap().to_bytes(), key.to_bytes());
+        assert!(create_key_file(&path, "00").is_err());
+        assert_eq!(load_key(&path).unwrap().to_bytes(), key.to_bytes());
+        fs::remove_file(&*path).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, prelude::*, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use rustls::{ServerConfig, ServerConnection};
+use crate::http::{self, create_response_with_type, header_value, with_header, write_response, Head};
+use crate::connection::Connection;
+use crate::server::ActiveConnection;
+
+// HTTPS termination. Setting UPDATESERVER_TLS_CERT and UPDATESERVER_TLS_KEY (PEM files) moves
+// the server to UPDATESERVER_HTTPS_ADDR. Plain HTTP is then only served when
+// UPDATESERVER_HTTP_REDIRECT is set, and only to redirect clients to HTTPS. SIGHUP reloads
+// the certificate and key, the new pair is used from the next connection on.
+const TLS_CERT_VAR:&str = "UPDATESERVER_TLS_CERT";
+const TLS_KEY_VAR:&str = "UPDATESERVER_TLS_KEY";
+const HTTPS_ADDR_VAR:&str = "UPDATESERVER_HTTPS_ADDR";
+const HTTP_REDIRECT_VAR:&str = "UPDATESERVER_HTTP_REDIRECT";
+const HTTPS_ADDR:&str = "127.0.0.1:7443";
+// Every redirect gets its own thread, a client that hasn't sent its request within the
+// timeout is dropped, however slowly it keeps sending
+const REDIRECT_TIMEOUT:Duration = Duration::from_secs(5);
+const MAX_REDIRECTS:usize = 256;
+
+pub struct TlsSettings{
+    pub cert_path:String,
+    pub key_path:String,
+    pub https_addr:String,
+    pub redirect:bool, // redirect plain HTTP to HTTPS
+}
+
+fn var(name:&str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+// None when TLS isn't configured
+pub fn settings() -> Option<TlsSettings> {
+    let cert_path = var(TLS_CERT_VAR)?;
+    let key_path = var(TLS_KEY_VAR).unwrap_or_else(|| cert_path.clone());
+    Some(TlsSettings{
+        cert_path,
+        key_path,
+        https_addr:var(HTTPS_ADDR_VAR).unwrap_or_else(|| String::from(HTTPS_ADDR)),
+        redirect:var(HTTP_REDIRECT_VAR).is_some_and(|redirect| redirect != "0" && redirect != "false"),
+    })
+}
+
+pub fn load_config(settings:&TlsSettings) -> Result<Arc<ServerConfig>, String> {
+    let open = |path:&str| File::open(path).map(BufReader::new).map_err(|err| format!("failed to open {path}: {err}"));
+    let certs = rustls_pemfile::certs(&mut open(&settings.cert_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("invalid certificate in {}: {err}", settings.cert_path))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", settings.cert_path));
+    }
+    let key = rustls_pemfile::private_key(&mut open(&settings.key_path)?)
+        .map_err(|err| format!("invalid private key in {}: {err}", settings.key_path))?
+        .ok_or(format!("no private key in {}", settings.key_path))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| format!("certificate and key don't match: {err}"))?;
+    Ok(Arc::new(config))
+}
+
+pub struct TlsAcceptor{
+    settings:TlsSettings,
+    config:Arc<ServerConfig>,
+    reload:Arc<AtomicBool>, // set by SIGHUP
+}
+
+impl TlsAcceptor{
+    pub fn new(settings:TlsSettings) -> Result<TlsAcceptor, String> {
+        let config = load_config(&settings)?;
+        let reload = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&reload))
+            .map_err(|err| format!("failed to watch SIGHUP: {err}"))?;
+        Ok(TlsAcceptor{ settings, config, reload })
+    }
+
+    pub fn https_addr(&self) -> &str {
+        &self.settings.https_addr
+    }
+
+    pub fn redirect(&self) -> bool {
+        self.settings.redirect
+    }
+
+    // A bad certificate on reload keeps the old one, the server must not go down over it
+    fn reload_if_requested(&mut self){
+        if !self.reload.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        match load_config(&self.settings) {
+            Ok(config) => {
+                self.config = config;
+                println!("Reloaded TLS certificate from {}", self.settings.cert_path);
+            },
+            Err(err) => println!("Keeping the current TLS certificate, reload failed: {}", err)
+        }
+    }
+
+    // The handshake itself runs on the first read of the request
+    pub fn accept(&mut self, stream:TcpStream) -> Result<Connection, String> {
+        self.reload_if_requested();
+        let connection = ServerConnection::new(Arc::clone(&self.config)).map_err(|err| err.to_string())?;
+        Ok(Connection::tls(connection, stream))
+    }
+}
+
+// Each read only waits for the time left until the deadline
+struct DeadlineReader<'a>{
+    stream:&'a TcpStream,
+    deadline:Instant,
+}
+
+impl Read for DeadlineReader<'_>{
+    fn read(&mut self, buf:&mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request not received in time"));
+        }
+        let mut stream = self.stream;
+        stream.set_read_timeout(Some(left))?;
+        stream.read(buf)
+    }
+}
+
+fn send_redirect(stream:&TcpStream, https_port:&str){
+    if let Err(err) = stream.set_write_timeout(Some(REDIRECT_TIMEOUT)) {
+        println!("Failed to set the redirect timeout: {}", err);
+        return;
+    }
+    let reader = DeadlineReader{ stream, deadline:Instant::now() + REDIRECT_TIMEOUT };
+    let raw = match http::read_request(&mut BufReader::new(reader)) {
+        Ok(Some(raw)) if raw.head == Head::Complete => raw,
+        Ok(_) => return,
+        Err(err) => {
+            println!("Failed to read the request to redirect: {}", err);
+            return;
+        }
+    };
+
+    let target = raw.request_line.split_whitespace().nth(1).unwrap_or("/");
+    let host = header_value(&raw.headers, "Host").unwrap_or_else(|| String::from("127.0.0.1"));
+    // drop the plain HTTP port, keep bracketed IPv6 hosts intact
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => name.to_string(),
+        _ => host
+    };
+    let location = format!("https://{host}:{https_port}{target}");
+    let response = with_header(create_response_with_type(308, "text/plain", ""), "Location", &location);
+    if let Err(err) = write_response(stream, response, false) {
+        println!("Failed to send HTTPS redirect: {}", err);
+    }
+}
+
+// Answers every plain HTTP request on the listener with a 308 to the same path on HTTPS
+pub fn spawn_redirect(listener:TcpListener, https_addr:&str){
+    let https_port = https_addr.rsplit_once(':').map(|(_, port)| port.to_string()).unwrap_or_else(|| String::from("443"));
+    match listener.local_addr() {
+        Ok(http_addr) => println!("Redirecting http://{} to HTTPS port {}", http_addr, https_port),
+        Err(err) => println!("Redirecting to HTTPS port {} from an unknown address: {}", https_port, err)
+    }
+    thread::spawn(move || {
+        let active = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming().flatten() {
+            if active.load(Ordering::SeqCst) >= MAX_REDIRECTS {
+                println!("Closing connection, {} redirects are in progress", MAX_REDIRECTS);
+                continue;
+            }
+            active.fetch_add(1, Ordering::SeqCst);
+            let guard = ActiveConnection(Arc::clone(&active));
+            let https_port = https_port.clone();
+            thread::spawn(move || {
+                let _guard = guard;
+                send_redirect(&stream, &https_port);
+            });
+        }
+    });
+}
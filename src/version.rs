@@ -1,10 +1,11 @@
-use std::{
-    io::{prelude::*, BufReader},
-    fmt
-};
+use std::{fs, fmt, io};
+use std::cmp::Reverse;
 use std::fmt::Debug;
 use serde::{Serialize, Deserialize};
-use serde_json;
+use crate::{Channel, SysRequirements};
+use crate::hardware::HardwareRequirements;
+use crate::mirror::Mirror;
+use crate::delta::Delta;
 
 #[derive(Serialize, Deserialize)]
 #[derive(Debug)]
@@ -15,6 +16,67 @@ pub struct Version{
     pub patch:i32,
     pub count:i32,   // Number of successful downloads of this version
     pub urls:Vec<String>,
+    #[serde(default)]
+    pub sha256:String, // Hex digest of the full package behind urls/mirrors, clients check the download against it
+    #[serde(default)]
+    pub size:u64, // Size of the full package in bytes
+    #[serde(default = "full_rollout")]
+    pub rollout:u32, // Percentage of clients (0-100) this version is offered to
+    #[serde(default)]
+    pub halted:bool, // Halted versions are never offered again
+    #[serde(default)]
+    pub rollback:String, // Version that clients still on this (halted) version are sent back to
+    #[serde(default)]
+    pub hardware:HardwareRequirements, // Minimum hardware needed to run this version
+    #[serde(default)]
+    pub builds:Vec<SysRequirements>, // Per-platform packages, falls back to urls when empty
+    #[serde(default)]
+    pub min_updater_version:f32, // Oldest client updater (Request.updaterversion) that can install this version
+    #[serde(default)]
+    pub mirrors:Vec<Mirror>, // Weighted download mirrors, urls are used with equal weights when empty
+    #[serde(default)]
+    pub deltas:Vec<Delta>, // Patches from previous versions to this one
+}
+
+fn full_rollout() -> u32 {
+    100
+}
+
+impl Version{
+    // Dotted version number used to identify a version in the catalog, e.g "0.3.1.0"
+    pub fn number(&self) -> String {
+        format!("{}.{}.{}.{}", self.major, self.minor, self.build, self.patch)
+    }
+
+    // Tuple used to order versions, newer versions compare greater
+    pub fn key(&self) -> (i32, i32, i32, i32) {
+        (self.major, self.minor, self.build, self.patch)
+    }
+
+    pub fn mirror_set(&self) -> Vec<Mirror> {
+        if !self.mirrors.is_empty() {
+            return self.mirrors.clone();
+        }
+        self.urls.iter().map(|url| Mirror::new(url)).collect()
+    }
+
+    pub fn matches(&self, number:&str) -> bool {
+        parse_number(number) == Some(self.key())
+    }
+}
+
+// Accepts both "major.minor.build" and "major.minor.build.patch"
+pub fn parse_number(number:&str) -> Option<(i32, i32, i32, i32)> {
+    let parts = number
+        .trim()
+        .split('.')
+        .map(|part| part.parse::<i32>().ok())
+        .collect::<Option<Vec<i32>>>()?;
+    match parts.as_slice() {
+        [major, minor, build] => Some((*major, *minor, *build, 0)),
+        [major, minor, build, patch] => Some((*major, *minor, *build, *patch)),
+        _ => None
+    }
 }
 
 impl fmt::Display for Version{
@@ -33,14 +95,112 @@ pub struct Versions{
     extended:Vec<Version>
 }
 
+impl Versions{
+    // Versions of a channel, newest first
+    pub fn channel(&self, channel:&Channel) -> &Vec<Version> {
+        match channel {
+            Channel::Stable => &self.stable,
+            Channel::Beta => &self.beta,
+            Channel::Dev => &self.dev,
+            Channel::Canary => &self.canary,
+            Channel::Extended => &self.extended,
+        }
+    }
+
+    pub fn channel_mut(&mut self, channel:&Channel) -> &mut Vec<Version> {
+        match channel {
+            Channel::Stable => &mut self.stable,
+            Channel::Beta => &mut self.beta,
+            Channel::Dev => &mut self.dev,
+            Channel::Canary => &mut self.canary,
+            Channel::Extended => &mut self.extended,
+        }
+    }
+
+    pub fn find_mut(&mut self, channel:&Channel, number:&str) -> Option<&mut Version> {
+        self.channel_mut(channel).iter_mut().find(|version| version.matches(number))
+    }
+
+    pub fn sort(&mut self){
+        for channel in [&mut self.stable, &mut self.beta, &mut self.dev, &mut self.canary, &mut self.extended] {
+            channel.sort_by_key(|version| Reverse(version.key()));
+        }
+    }
+}
+
+// Catalog to serve from, see parse_versions
+pub fn load_versions(path:&str) -> Result<Versions, String> {
+    let mut versions = read_versions(path)?;
+    versions.sort();
+    Ok(versions)
+}
+
+// Catalog to change and save, the channels keep the order of the file so saving it doesn't
+// reorder what the operator wrote
+pub fn read_versions(path:&str) -> Result<Versions, String> {
+    let contents = fs::read_to_string(path).map_err(|err| format!("failed to read {path}: {err}"))?;
+    serde_json::from_str::<Versions>(&contents).map_err(|err| format!("failed to parse {path}: {err}"))
+}
+
+// Channels are sorted newest first, version selection relies on that order
+pub fn parse_versions(contents:&str) -> Result<Versions, serde_json::Error> {
+    let mut versions = serde_json::from_str::<Versions>(contents)?;
+    versions.sort();
+    Ok(versions)
+}
+
+// Writes the catalog back to disk so admin changes survive a restart. The new file replaces
+// the old one in a single rename, a server reloading meanwhile never reads half a catalog.
+pub fn save_versions(path:&str, versions:&Versions) -> io::Result<()> {
+    let contents = serde_json::to_string_pretty(versions)?;
+    let temporary = format!("{path}.tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
+
+// The server's admin endpoints and the delta subcommand both write catalog files. A writer
+// holds the lock of the file while it reads the file again, changes it and saves it, so
+// neither overwrites what the other saved. The lock is released when it's dropped.
+pub struct CatalogLock{
+    _file:fs::File,
+}
+
+pub fn lock_versions(path:&str) -> io::Result<CatalogLock> {
+    let file = fs::OpenOptions::new().create(true).truncate(false).write(true).open(format!("{path}.lock"))?;
+    file.lock()?;
+    Ok(CatalogLock{ _file:file })
+}
+
 impl fmt::Display for Versions {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Versions:\n")?;
-        write!(f, "  Dev:    {:?}\n", self.dev)?;
-        write!(f, "  Stable: {:?}\n", self.stable)?;
-        write!(f, "  Beta:   {:?}\n", self.beta)?;
-        write!(f, "  Canary: {:?}\n", self.canary)?;
-        write!(f, "  Extended: {:?}\n", self.extended)?;
+        writeln!(f, "Versions:")?;
+        writeln!(f, "  Dev:    {:?}", self.dev)?;
+        writeln!(f, "  Stable: {:?}", self.stable)?;
+        writeln!(f, "  Beta:   {:?}", self.beta)?;
+        writeln!(f, "  Canary: {:?}", self.canary)?;
+        writeln!(f, "  Extended: {:?}", self.extended)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_are_sorted_newest_first() {
+        let versions = parse_versions(r#"{
+            "stable":[],
+            "beta":[],
+            "dev":[
+                {"major":1,"minor":1,"build":5,"patch":0,"count":0,"urls":[]},
+                {"major":1,"minor":2,"build":0,"patch":0,"count":0,"urls":[]},
+                {"major":1,"minor":10,"build":0,"patch":0,"count":0,"urls":[]}
+            ],
+            "canary":[],
+            "extended":[]
+        }"#).unwrap();
+        let numbers = versions.channel(&Channel::Dev).iter().map(Version::number).collect::<Vec<String>>();
+        assert_eq!(numbers, ["1.10.0.0", "1.2.0.0", "1.1.5.0"]);
+    }
+}
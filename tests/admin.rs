@@ -0,0 +1,185 @@
+// The admin endpoints over HTTP: the token check and the catalog changes they make
+mod common;
+
+use std::fs;
+use serde_json::{json, Value};
+use common::{client_request, TestResponse, TestServer, CATALOG};
+use updateserver::{Channel, ServerConfig};
+
+const TOKEN:&str = "admin-secret";
+
+fn admin_server() -> TestServer {
+    TestServer::with_config(CATALOG, ServerConfig{ admin_token:Some(String::from(TOKEN)), ..Default::default() })
+}
+
+fn admin(server:&TestServer, path:&str, token:&str, body:&Value) -> TestResponse {
+    server.send_with_headers("POST", path, &[("X-Admin-Token", token)], &body.to_string())
+}
+
+fn latest_status(server:&TestServer, channel:Channel) -> String {
+    let mut request = client_request(channel);
+    request.installid = String::from("install-1");
+    let latest = server.post("/latest", &serde_json::to_value(&request).unwrap());
+    latest.json()["status"].as_str().unwrap().to_string()
+}
+
+#[test]
+fn rollout_needs_the_token() {
+    let server = admin_server();
+    let body = json!({"channel":"Stable","version":"1.0.0","percentage":0});
+    assert_eq!(server.post("/admin/rollout", &body).status_code, 403);
+    assert_eq!(admin(&server, "/admin/rollout", "admin-secreT", &body).status_code, 403);
+    assert_eq!(admin(&server, "/admin/rollout", "admin-secret-and-more", &body).status_code, 403);
+    assert_eq!(latest_status(&server, Channel::Stable), "ok");
+
+    // without a configured token nobody gets in
+    let server = TestServer::start();
+    assert_eq!(admin(&server, "/admin/rollout", TOKEN, &body).status_code, 403);
+    assert_eq!(admin(&server, "/admin/rollout", "", &body).status_code, 403);
+}
+
+#[test]
+fn rollout_changes_the_offer_and_the_catalog_file() {
+    let server = admin_server();
+    let response = admin(&server, "/admin/rollout", TOKEN, &json!({"channel":"Stable","version":"1.0.0","percentage":0}));
+    assert_eq!(response.status_code, 200, "{}", response.body);
+    assert_eq!(response.json()["ok"], true);
+    assert_eq!(latest_status(&server, Channel::Stable), "noupdate");
+    let saved = serde_json::from_str::<Value>(&fs::read_to_string(server.versions_path()).unwrap()).unwrap();
+    assert_eq!(saved["stable"][0]["rollout"], 0);
+
+    let response = admin(&server, "/admin/rollout", TOKEN, &json!({"channel":"Stable","version":"1.0.0","percentage":100}));
+    assert_eq!(response.status_code, 200);
+    assert_eq!(latest_status(&server, Channel::Stable), "ok");
+}
+
+#[test]
+fn rollout_rejects_bad_requests() {
+    let server = admin_server();
+    let response = admin(&server, "/admin/rollout", TOKEN, &json!({"channel":"Stable","version":"1.0.0","percentage":101}));
+    assert_eq!(response.status_code, 400);
+    let response = admin(&server, "/admin/rollout", TOKEN, &json!({"channel":"Stable","version":"9.9.9","percentage":50}));
+    assert_eq!(response.status_code, 404);
+    let response = admin(&server, "/admin/rollout", TOKEN, &json!({"channel":"Stable","percentage":50}));
+    assert_eq!(response.status_code, 400);
+    assert_eq!(server.send("GET", "/admin/rollout", "").status_code, 405);
+}
+
+fn latest_for(server:&TestServer, version:&str) -> Value {
+    let mut request = client_request(Channel::Dev);
+    request.version = String::from(version);
+    server.post("/latest", &serde_json::to_value(&request).unwrap()).json()
+}
+
+#[test]
+fn halt_and_rollback_need_the_token() {
+    let server = admin_server();
+    for path in ["/admin/halt", "/admin/rollback"] {
+        let body = json!({"channel":"Dev","version":"1.2.0"});
+        assert_eq!(server.post(path, &body).status_code, 403, "{path}");
+        assert_eq!(admin(&server, path, "wrong", &body).status_code, 403, "{path}");
+    }
+    assert_eq!(latest_for(&server, "")["info"], "update to 1.2.0.0");
+    assert_eq!(latest_for(&server, "1.2.0")["status"], "noupdate");
+}
+
+#[test]
+fn halted_version_is_no_longer_offered() {
+    let server = admin_server();
+    let response = admin(&server, "/admin/halt", TOKEN, &json!({"channel":"Dev","version":"1.2.0"}));
+    assert_eq!(response.status_code, 200, "{}", response.body);
+    assert_eq!(latest_for(&server, "")["info"], "update to 1.1.5.0");
+    let saved = serde_json::from_str::<Value>(&fs::read_to_string(server.versions_path()).unwrap()).unwrap();
+    assert_eq!(saved["dev"][0]["halted"], true);
+
+    // clients already on the halted version aren't moved without a rollback target
+    assert_eq!(latest_for(&server, "1.2.0")["status"], "noupdate");
+
+    let response = admin(&server, "/admin/halt", TOKEN, &json!({"channel":"Dev","version":"1.2.0","halted":false}));
+    assert_eq!(response.status_code, 200);
+    assert_eq!(latest_for(&server, "")["info"], "update to 1.2.0.0");
+}
+
+#[test]
+fn clients_on_a_rolled_back_version_are_downgraded() {
+    let server = admin_server();
+    let response = admin(&server, "/admin/rollback", TOKEN, &json!({"channel":"Dev","version":"1.2.0"}));
+    assert_eq!(response.status_code, 200, "{}", response.body);
+    assert!(response.json()["info"].as_str().unwrap().ends_with("rolled back to 1.1.5.0"));
+
+    let mut request = client_request(Channel::Dev);
+    request.version = String::from("1.2.0");
+    let download = server.start_download(&mut request);
+    let json = download.json();
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["downgrade"], true);
+    assert_eq!(json["downloadlink"], "https://example.com/dev-old.tar.gz");
+
+    // everyone else gets the last good version as a plain update
+    let json = latest_for(&server, "1.0.0");
+    assert_eq!(json["info"], "update to 1.1.5.0");
+    assert_eq!(json["downgrade"], false);
+
+    let response = admin(&server, "/admin/rollback", TOKEN, &json!({"channel":"Dev","version":"1.1.5","target":"1.2.0"}));
+    assert_eq!(response.status_code, 409);
+}
+
+// What `updateserver delta` does to the catalog file while the server runs
+fn add_delta_on_disk(server:&TestServer) {
+    let mut saved = serde_json::from_str::<Value>(&fs::read_to_string(server.versions_path()).unwrap()).unwrap();
+    saved["dev"][0]["deltas"] = json!([{"from":"1.1.5","url":"https://example.com/dev.from-1.1.5.patch","sha256":"aaaa","size":64}]);
+    fs::write(server.versions_path(), saved.to_string()).unwrap();
+}
+
+#[test]
+fn reload_serves_the_catalog_files() {
+    let server = admin_server();
+    add_delta_on_disk(&server);
+    assert!(latest_for(&server, "1.1.5").get("delta").is_none());
+
+    assert_eq!(server.post("/admin/reload", &json!({})).status_code, 403);
+    let response = admin(&server, "/admin/reload", TOKEN, &json!({}));
+    assert_eq!(response.status_code, 200, "{}", response.body);
+    assert_eq!(response.json()["info"], "1 products reloaded");
+    assert_eq!(latest_for(&server, "1.1.5")["delta"]["url"], "https://example.com/dev.from-1.1.5.patch");
+
+    // a broken catalog keeps the server on the one it has
+    fs::write(server.versions_path(), "{\"dev\":").unwrap();
+    let response = admin(&server, "/admin/reload", TOKEN, &json!({}));
+    assert_eq!(response.status_code, 500);
+    assert!(response.json()["info"].as_str().unwrap().starts_with("reload failed"), "{}", response.body);
+    assert_eq!(latest_for(&server, "1.1.5")["delta"]["url"], "https://example.com/dev.from-1.1.5.patch");
+}
+
+#[test]
+fn admin_changes_keep_what_is_on_disk() {
+    let server = admin_server();
+    add_delta_on_disk(&server);
+
+    let response = admin(&server, "/admin/rollout", TOKEN, &json!({"channel":"Stable","version":"1.0.0","percentage":0}));
+    assert_eq!(response.status_code, 200, "{}", response.body);
+    let saved = serde_json::from_str::<Value>(&fs::read_to_string(server.versions_path()).unwrap()).unwrap();
+    assert_eq!(saved["stable"][0]["rollout"], 0);
+    assert_eq!(saved["dev"][0]["deltas"][0]["from"], "1.1.5");
+    // and the server answers from the changed file
+    assert_eq!(latest_for(&server, "1.1.5")["delta"]["from"], "1.1.5");
+}
+
+#[test]
+fn admin_changes_keep_the_order_of_the_file() {
+    // the operator lists dev oldest first
+    let mut catalog = serde_json::from_str::<Value>(CATALOG).unwrap();
+    catalog["dev"].as_array_mut().unwrap().reverse();
+    let server = TestServer::with_config(&catalog.to_string(), ServerConfig{ admin_token:Some(String::from(TOKEN)), ..Default::default() });
+
+    let response = admin(&server, "/admin/rollout", TOKEN, &json!({"channel":"Dev","version":"1.2.0","percentage":0}));
+    assert_eq!(response.status_code, 200, "{}", response.body);
+    let saved = serde_json::from_str::<Value>(&fs::read_to_string(server.versions_path()).unwrap()).unwrap();
+    assert_eq!(saved["dev"][0]["build"], 5);
+    assert_eq!(saved["dev"][1]["rollout"], 0);
+
+    // the server still picks from the newest down, and rolls back to the version before
+    assert_eq!(latest_for(&server, "")["info"], "update to 1.1.5.0");
+    let response = admin(&server, "/admin/rollback", TOKEN, &json!({"channel":"Dev","version":"1.2.0"}));
+    assert!(response.json()["info"].as_str().unwrap().ends_with("rolled back to 1.1.5.0"), "{}", response.body);
+}
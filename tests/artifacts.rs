@@ -0,0 +1,153 @@
+// Package files served from the artifact store, whole, by range or not at all
+mod common;
+
+use std::fs;
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use common::{TestServer, CATALOG};
+use updateserver::ServerConfig;
+use updateserver::http::header_value;
+
+const PACKAGE:&[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+const PATH:&str = "/artifacts/linux/hypertrail-1.2.0.bin";
+
+// The store is dir/store, next to a file it must not serve; tests remove dir when they're done
+fn artifact_server(name:&str) -> (TestServer, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("updateserver-artifacts-{}-{name}", std::process::id()));
+    let store = dir.join("store");
+    fs::create_dir_all(store.join("linux")).unwrap();
+    fs::write(store.join("linux").join("hypertrail-1.2.0.bin"), PACKAGE).unwrap();
+    fs::write(dir.join("secret"), "secret").unwrap();
+    let server = TestServer::with_config(CATALOG, ServerConfig{ artifacts_dir:store, ..Default::default() });
+    (server, dir)
+}
+
+#[test]
+fn whole_file_with_an_etag() {
+    let (server, dir) = artifact_server("whole");
+    let response = server.send("GET", PATH, "");
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.body.as_bytes(), PACKAGE);
+    assert_eq!(response.header("Content-Length"), Some(PACKAGE.len().to_string()));
+    assert_eq!(response.header("Content-Type").as_deref(), Some("application/octet-stream"));
+    assert_eq!(response.header("Accept-Ranges").as_deref(), Some("bytes"));
+    let etag = response.header("ETag").unwrap();
+    assert!(etag.starts_with('"') && etag.ends_with('"'), "{etag}");
+    assert_eq!(server.send("GET", PATH, "").header("ETag"), Some(etag));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn ranges() {
+    let (server, dir) = artifact_server("ranges");
+    let response = server.send_with_headers("GET", PATH, &[("Range", "bytes=10-19")], "");
+    assert_eq!(response.status_code, 206);
+    assert_eq!(response.body, "abcdefghij");
+    assert_eq!(response.header("Content-Range").as_deref(), Some("bytes 10-19/36"));
+    assert_eq!(response.header("Content-Length").as_deref(), Some("10"));
+
+    let response = server.send_with_headers("GET", PATH, &[("Range", "bytes=-6")], "");
+    assert_eq!(response.status_code, 206);
+    assert_eq!(response.body, "uvwxyz");
+    assert_eq!(response.header("Content-Range").as_deref(), Some("bytes 30-35/36"));
+
+    let response = server.send_with_headers("GET", PATH, &[("Range", "bytes=36-")], "");
+    assert_eq!(response.status_code, 416);
+    assert_eq!(response.header("Content-Range").as_deref(), Some("bytes */36"));
+    assert!(response.body.is_empty());
+
+    // a client whose partial copy is outdated gets the whole file
+    let response = server.send_with_headers("GET", PATH, &[("Range", "bytes=10-19"), ("If-Range", "\"outdated\"")], "");
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.body.as_bytes(), PACKAGE);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn not_modified() {
+    let (server, dir) = artifact_server("not-modified");
+    let etag = server.send("GET", PATH, "").header("ETag").unwrap();
+    let response = server.send_with_headers("GET", PATH, &[("If-None-Match", &etag)], "");
+    assert_eq!(response.status_code, 304);
+    assert_eq!(response.header("ETag"), Some(etag));
+    assert!(response.body.is_empty());
+
+    let response = server.send_with_headers("GET", PATH, &[("If-None-Match", "\"other\"")], "");
+    assert_eq!(response.status_code, 200);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn head_has_no_body() {
+    let (server, dir) = artifact_server("head");
+    let response = server.send("HEAD", PATH, "");
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.header("Content-Length"), Some(PACKAGE.len().to_string()));
+    assert!(response.header("ETag").is_some());
+    assert!(response.body.is_empty());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn paths_outside_the_store() {
+    let (server, dir) = artifact_server("outside");
+    for path in [
+        "/artifacts/../secret",
+        "/artifacts/linux/../../secret",
+        "/artifacts/./linux/../../secret",
+        "/artifacts//etc/passwd",
+        "/artifacts/",
+        "/artifacts/linux",
+        "/artifacts/linux/missing.bin",
+        // the prefix is only taken off once
+        "/artifacts//artifacts/linux/hypertrail-1.2.0.bin",
+        "/artifacts/artifacts/linux/hypertrail-1.2.0.bin",
+        // encoded separators and dots are checked after decoding
+        "/artifacts/..%2Fsecret",
+        "/artifacts/linux%2F..%2F..%2Fsecret",
+        "/artifacts/%2e%2e/secret",
+    ] {
+        let response = server.send("GET", path, "");
+        assert_eq!(response.status_code, 404, "{path}");
+        assert_eq!(response.body, "artifact not found", "{path}");
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn percent_encoded_names() {
+    let (server, dir) = artifact_server("encoded");
+    fs::write(dir.join("store").join("linux").join("hypertrail 1.2.0+1.bin"), PACKAGE).unwrap();
+    let response = server.send("GET", "/artifacts/linux/hypertrail%201.2.0+1.bin", "");
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.body.as_bytes(), PACKAGE);
+    assert_eq!(server.send("GET", "/artifacts/linux%2Fhypertrail-1.2.0.bin", "").status_code, 200);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+// The head of a response as text, its body is compressed and isn't
+fn head_of(server:&TestServer, method:&str, path:&str) -> String {
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(stream, "{method} {path} HTTP/1.1\r\nHost: a\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = vec![];
+    stream.read_to_end(&mut response).unwrap();
+    let end = response.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+    String::from_utf8_lossy(&response[..end + 2]).into_owned()
+}
+
+// HEAD takes the same compression decision as GET, so its headers describe the GET body
+#[test]
+fn head_matches_get_for_compressible_files() {
+    let (server, dir) = artifact_server("head-compressed");
+    fs::write(dir.join("store").join("notes.txt"), "release notes\n".repeat(200)).unwrap();
+    let get = head_of(&server, "GET", "/artifacts/notes.txt");
+    let head = head_of(&server, "HEAD", "/artifacts/notes.txt");
+    assert_eq!(header_value(&get, "Content-Encoding").as_deref(), Some("gzip"), "{get}");
+    for name in ["Content-Length", "Content-Encoding", "Content-Type", "ETag", "Vary"] {
+        assert_eq!(header_value(&head, name), header_value(&get, name), "{name}");
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
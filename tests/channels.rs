@@ -0,0 +1,63 @@
+// Every release channel is answered from its own list in the catalog
+mod common;
+
+use common::{client_request, status_request, TestServer};
+use updateserver::{Action, Channel};
+
+const CHANNELS:[(Channel, &str, &str); 5] = [
+    (Channel::Stable, "1.0.0.0", "https://example.com/stable.tar.gz"),
+    (Channel::Beta, "1.1.0.0", "https://example.com/beta.tar.gz"),
+    (Channel::Dev, "1.2.0.0", "https://example.com/dev.tar.gz"),
+    (Channel::Canary, "1.3.0.0", "https://example.com/canary.tar.gz"),
+    (Channel::Extended, "0.9.0.0", "https://example.com/extended.tar.gz"),
+];
+
+#[test]
+fn every_channel_offers_its_own_version() {
+    let server = TestServer::start();
+    for (channel, version, downloadlink) in CHANNELS {
+        let mut request = client_request(channel.clone());
+        let download = server.start_download(&mut request);
+        assert_eq!(download.status_code, 200, "{channel}");
+        let json = download.json();
+        assert_eq!(json["status"], "ok", "{channel}");
+        assert_eq!(json["info"], format!("update to {version}"), "{channel}");
+        assert_eq!(json["downloadlink"], downloadlink, "{channel}");
+
+        let status = server.post("/status", &status_request(&request, Action::retry, 1));
+        assert_eq!(status.json()["status"], "ok", "{channel}");
+    }
+}
+
+#[test]
+fn every_channel_by_query() {
+    let server = TestServer::start();
+    for (channel, version, _) in CHANNELS {
+        let latest = server.send("GET", &format!("/latest?updater=hypertrail&acceptformat=json&os.platform=Linux&os.arch=x86&channel={channel}"), "");
+        assert_eq!(latest.status_code, 200, "{channel}");
+        assert_eq!(latest.json()["info"], format!("update to {version}"), "{channel}");
+    }
+}
+
+#[test]
+fn up_to_date_on_every_channel() {
+    let server = TestServer::start();
+    for (channel, version, _) in CHANNELS {
+        let mut request = client_request(channel.clone());
+        request.version = String::from(version);
+        let latest = server.post("/latest", &serde_json::to_value(&request).unwrap());
+        assert_eq!(latest.status_code, 200, "{channel}");
+        let json = latest.json();
+        assert_eq!(json["status"], "noupdate", "{channel}");
+        assert_eq!(json["actions"], serde_json::json!([]), "{channel}");
+    }
+}
+
+#[test]
+fn older_dev_client_gets_the_newest_dev_version() {
+    let server = TestServer::start();
+    let mut request = client_request(Channel::Dev);
+    request.version = String::from("1.1.5");
+    let latest = server.post("/latest", &serde_json::to_value(&request).unwrap());
+    assert_eq!(latest.json()["info"], "update to 1.2.0.0");
+}
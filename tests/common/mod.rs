@@ -0,0 +1,198 @@
+// Shared by the integration tests: a server on an ephemeral port with its own catalog, and a
+// minimal HTTP client that sends one request per connection.
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::{prelude::*, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use serde_json::Value;
+use updateserver::{catalog, default_request, Action, Channel, EventType, Request, Server, ServerConfig, StatusRequest};
+use updateserver::connection::{Connection, KeepAlive};
+
+pub const PRODUCT:&str = "hypertrail";
+
+// One version per channel, so every channel can be told apart by what it offers
+pub const CATALOG:&str = r#"{
+    "stable":[{"major":1,"minor":0,"build":0,"patch":0,"count":0,"urls":["https://example.com/stable.tar.gz"],
+        "sha256":"9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08","size":4}],
+    "beta":[{"major":1,"minor":1,"build":0,"patch":0,"count":0,"urls":["https://example.com/beta.tar.gz"]}],
+    "dev":[
+        {"major":1,"minor":2,"build":0,"patch":0,"count":0,"urls":["https://example.com/dev.tar.gz"]},
+        {"major":1,"minor":1,"build":5,"patch":0,"count":0,"urls":["https://example.com/dev-old.tar.gz"]}
+    ],
+    "canary":[{"major":1,"minor":3,"build":0,"patch":0,"count":0,"urls":["https://example.com/canary.tar.gz"]}],
+    "extended":[{"major":0,"minor":9,"build":0,"patch":0,"count":0,"urls":["https://example.com/extended.tar.gz"]}]
+}"#;
+
+// A component of the product, its beta release has no package yet
+pub const COMPONENT:&str = "widevine";
+pub const COMPONENT_CATALOG:&str = r#"{
+    "stable":[], "canary":[], "extended":[],
+    "dev":[{"major":4,"minor":10,"build":0,"patch":0,"count":0,"urls":["https://example.com/widevine.zip"],"sha256":"abcd","size":2048}],
+    "beta":[{"major":4,"minor":9,"build":0,"patch":0,"count":0,"urls":[]}]
+}"#;
+
+static SERVERS:AtomicUsize = AtomicUsize::new(0);
+
+pub struct TestServer{
+    pub addr:SocketAddr,
+    dir:PathBuf,
+}
+
+impl TestServer{
+    // Every test gets its own server, sessions don't leak between tests
+    pub fn start() -> TestServer {
+        TestServer::with_catalog(CATALOG)
+    }
+
+    pub fn with_catalog(catalog:&str) -> TestServer {
+        TestServer::with_config(catalog, ServerConfig::default())
+    }
+
+    pub fn with_config(catalog:&str, config:ServerConfig) -> TestServer {
+        let dir = std::env::temp_dir().join(format!("updateserver-test-{}-{}", std::process::id(), SERVERS.fetch_add(1, Ordering::SeqCst)));
+        fs::create_dir_all(&dir).unwrap();
+        let versions_path = dir.join("versions.json");
+        fs::write(&versions_path, catalog).unwrap();
+        fs::create_dir_all(dir.join("catalogs")).unwrap();
+        fs::write(dir.join("catalogs").join(format!("{COMPONENT}.json")), COMPONENT_CATALOG).unwrap();
+        let products = catalog::load_products(
+            &dir.join("products.json").to_string_lossy(),
+            &versions_path.to_string_lossy(),
+            &dir.join("catalogs").to_string_lossy()
+        ).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let keep_alive = KeepAlive{ idle_timeout:Duration::from_secs(1), max_requests:10, request_timeout:Duration::from_secs(2) };
+            Arc::new(Server::new(products, config)).serve(listener, |stream| Ok(Connection::plain(stream)), keep_alive, 16);
+        });
+        TestServer{ addr, dir }
+    }
+
+    pub fn send(&self, method:&str, target:&str, body:&str) -> TestResponse {
+        self.send_with_headers(method, target, &[], body)
+    }
+
+    pub fn send_with_headers(&self, method:&str, target:&str, headers:&[(&str, &str)], body:&str) -> TestResponse {
+        let headers = headers.iter().map(|(name, value)| format!("{name}: {value}\r\n")).collect::<String>();
+        let request = format!("{method} {target} HTTP/1.1\r\nHost: {}\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}", self.addr, body.len());
+        self.send_raw(request.as_bytes())
+    }
+
+    pub fn post(&self, target:&str, body:&Value) -> TestResponse {
+        self.send("POST", target, &body.to_string())
+    }
+
+    // Writes the bytes as they are, for requests the client above can't produce
+    pub fn send_raw(&self, request:&[u8]) -> TestResponse {
+        let mut stream = TcpStream::connect(self.addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(request).unwrap();
+        TestResponse::read(&mut BufReader::new(stream))
+    }
+
+    // Like send_raw, then closes the sending side, the server reads the end of the stream after the bytes
+    pub fn send_raw_then_close(&self, request:&[u8]) -> TestResponse {
+        let mut stream = TcpStream::connect(self.addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(request).unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        TestResponse::read(&mut BufReader::new(stream))
+    }
+
+    // latest, then download with the ids of the answer, returns the download response
+    pub fn start_download(&self, request:&mut Request) -> TestResponse {
+        let latest = self.post("/latest", &serde_json::to_value(&*request).unwrap());
+        assert_eq!(latest.status_code, 200, "{}", latest.body);
+        latest.use_ids(request);
+        let download = self.post("/download", &serde_json::to_value(&*request).unwrap());
+        download.use_ids(request);
+        download
+    }
+}
+
+impl TestServer{
+    // The catalog file of the product, admin changes are written back to it
+    pub fn versions_path(&self) -> PathBuf {
+        self.dir.join("versions.json")
+    }
+}
+
+impl Drop for TestServer{
+    fn drop(&mut self){
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub struct TestResponse{
+    pub status_code:u16,
+    pub headers:String,
+    pub body:String,
+}
+
+impl TestResponse{
+    fn read(reader:&mut impl BufRead) -> TestResponse {
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line).unwrap();
+        let status_code = status_line.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok())
+            .unwrap_or_else(|| panic!("no status line in {status_line:?}"));
+        let mut headers = String::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                break;
+            }
+            headers.push_str(&line);
+        }
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        TestResponse{ status_code, headers, body }
+    }
+
+    pub fn header(&self, name:&str) -> Option<String> {
+        updateserver::http::header_value(&self.headers, name)
+    }
+
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap_or_else(|err| panic!("{err} in {:?}", self.body))
+    }
+
+    // Continues the session with the ids of this response
+    pub fn use_ids(&self, request:&mut Request) {
+        let json = self.json();
+        request.sessionid = json["sessionid"].as_str().unwrap().to_string();
+        request.requestid = json["requestid"].as_str().unwrap().to_string();
+    }
+}
+
+// A Linux x86 client on the Dev channel that runs no version yet
+pub fn client_request(channel:Channel) -> Request {
+    let mut request = default_request();
+    request.updater = String::from(PRODUCT);
+    request.acceptformat = String::from("json");
+    request.os.platform = String::from("Linux");
+    request.os.arch = String::from("x86");
+    request.os.dedup = String::from("test");
+    request.updaterversion = 0.1;
+    request.channel = channel;
+    request
+}
+
+pub fn status_request(request:&Request, action:Action, result:i32) -> Value {
+    let status_request = StatusRequest{
+        request:request.clone(),
+        eventtype:EventType::Download,
+        action,
+        result,
+        mirror:String::new(),
+        delta:false
+    };
+    serde_json::to_value(&status_request).unwrap()
+}
@@ -0,0 +1,50 @@
+// Response bodies are compressed with the coding the client prefers, and every response that
+// could have been compressed says it varies with Accept-Encoding
+mod common;
+
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::time::Duration;
+use common::{TestServer, CATALOG};
+use updateserver::ServerConfig;
+
+const LATEST:&str = "/latest?updater=hypertrail&os.platform=Linux&os.arch=x86&channel=Dev";
+
+// The compressed body isn't text, so the response is read as bytes
+fn get(server:&TestServer, accept_encoding:&str) -> (String, Vec<u8>) {
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(stream, "GET {LATEST} HTTP/1.1\r\nHost: a\r\nAccept-Encoding: {accept_encoding}\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = vec![];
+    stream.read_to_end(&mut response).unwrap();
+    let end = response.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+    (String::from_utf8_lossy(&response[..end + 2]).into_owned(), response[end + 4..].to_vec())
+}
+
+fn header(head:&str, name:&str) -> Option<String> {
+    updateserver::http::header_value(head, name)
+}
+
+#[test]
+fn compressed_for_the_client() {
+    let server = TestServer::with_config(CATALOG, ServerConfig{ compression_min_size:16, ..Default::default() });
+    let (head, body) = get(&server, "br;q=0.5, gzip");
+    assert_eq!(header(&head, "Content-Encoding").as_deref(), Some("gzip"), "{head}");
+    // the format came from the (missing) Accept header, both go in one Vary
+    assert_eq!(header(&head, "Vary").as_deref(), Some("Accept, Accept-Encoding"));
+    let mut decoded = String::new();
+    flate2::read::GzDecoder::new(&body[..]).read_to_string(&mut decoded).unwrap();
+    assert!(decoded.contains("\"info\":\"update to 1.2.0.0\""), "{decoded}");
+}
+
+#[test]
+fn uncompressed_responses_vary_too() {
+    // the /latest answer is below the default minimum size
+    let server = TestServer::start();
+    for accept_encoding in ["gzip", "identity", "gzip;q=0"] {
+        let (head, body) = get(&server, accept_encoding);
+        assert_eq!(header(&head, "Content-Encoding"), None, "{accept_encoding}");
+        assert_eq!(header(&head, "Vary").as_deref(), Some("Accept, Accept-Encoding"), "{accept_encoding}");
+        assert!(body.starts_with(b"{"), "{accept_encoding}");
+    }
+}
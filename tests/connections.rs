@@ -0,0 +1,65 @@
+// Persistent connections are served side by side
+mod common;
+
+use std::io::{prelude::*, ErrorKind};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use common::{client_request, TestServer};
+use updateserver::Channel;
+
+// The test server closes idle connections after a second, a client held up behind the idle
+// one would only be answered after that
+#[test]
+fn idle_connection_does_not_block_others() {
+    let server = TestServer::start();
+    let mut idle = TcpStream::connect(server.addr).unwrap();
+    idle.write_all(format!("GET /publickey HTTP/1.1\r\nHost: {}\r\n\r\n", server.addr).as_bytes()).unwrap();
+    idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut answer = [0; 12];
+    idle.read_exact(&mut answer).unwrap();
+    assert_eq!(&answer, b"HTTP/1.1 404");
+
+    let started = Instant::now();
+    let response = server.post("/latest", &serde_json::to_value(client_request(Channel::Dev)).unwrap());
+    assert_eq!(response.status_code, 200);
+    assert!(started.elapsed() < Duration::from_millis(500), "answered after {:?}", started.elapsed());
+}
+
+// Sends the start of a request, then a byte of the rest every 200ms, well inside the idle
+// timeout, and reads what comes back until the server closes the connection
+fn trickle(server:&TestServer, start:&[u8], rest:&[u8]) -> String {
+    let mut client = TcpStream::connect(server.addr).unwrap();
+    client.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    client.write_all(start).unwrap();
+    let started = Instant::now();
+    let mut received = vec![];
+    for byte in rest.iter().cycle() {
+        assert!(started.elapsed() < Duration::from_secs(10), "connection still open after {:?}", started.elapsed());
+        let _ = client.write_all(&[*byte]);
+        let mut buffer = [0; 1024];
+        match client.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => received.extend_from_slice(&buffer[..read]),
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {},
+            Err(_) => break
+        }
+    }
+    String::from_utf8_lossy(&received).into_owned()
+}
+
+// The test server gives a request two seconds from its first byte
+#[test]
+fn trickled_head_times_out() {
+    let server = TestServer::start();
+    let response = trickle(&server, b"G", b"ET /latest HTTP/1.1\r\nHost: localhost\r\nX-Slow: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+    assert!(response.starts_with("HTTP/1.1 408"), "{response}");
+    assert!(response.ends_with("request head not received in time"), "{response}");
+}
+
+#[test]
+fn trickled_body_times_out() {
+    let server = TestServer::start();
+    let response = trickle(&server, b"POST /latest HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1000\r\n\r\n", b" ");
+    assert!(response.starts_with("HTTP/1.1 408"), "{response}");
+    assert!(response.ends_with("request body not received in time"), "{response}");
+}
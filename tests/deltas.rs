@@ -0,0 +1,97 @@
+// Delta patches are offered to clients on the version they patch, until the patch fails
+mod common;
+
+use common::{client_request, status_request, TestServer};
+use serde_json::json;
+use updateserver::{Action, Channel, Request};
+
+// 1.2.0 has a patch from 1.1.5 for every platform and one from 1.1.0 for Windows only
+const DELTA_CATALOG:&str = r#"{
+    "stable":[], "beta":[], "canary":[], "extended":[],
+    "dev":[
+        {"major":1,"minor":2,"build":0,"patch":0,"count":0,"urls":["https://example.com/dev.tar.gz"],
+            "deltas":[
+                {"from":"1.1.5","url":"https://example.com/dev-from-1.1.5.patch","sha256":"aaaa","size":64,"format":"zstd-patch"},
+                {"from":"1.1.0","url":"https://example.com/dev-from-1.1.0.patch","sha256":"bbbb","size":96,"platform":"Windows"}
+            ]},
+        {"major":1,"minor":1,"build":5,"patch":0,"count":0,"urls":["https://example.com/dev-old.tar.gz"]}
+    ]
+}"#;
+
+fn client_on(version:&str) -> Request {
+    let mut request = client_request(Channel::Dev);
+    request.version = String::from(version);
+    request
+}
+
+fn delta_status(request:&Request, delta:bool) -> serde_json::Value {
+    let mut status = status_request(request, Action::retry, 0);
+    status["delta"] = json!(delta);
+    status
+}
+
+#[test]
+fn delta_for_the_client_version() {
+    let server = TestServer::with_catalog(DELTA_CATALOG);
+    let mut request = client_on("1.1.5");
+    let expected = json!({"from":"1.1.5","url":"https://example.com/dev-from-1.1.5.patch","sha256":"aaaa","size":64,"format":"zstd-patch"});
+
+    let latest = server.post("/latest", &serde_json::to_value(&request).unwrap());
+    assert_eq!(latest.status_code, 200);
+    assert_eq!(latest.json()["delta"], expected);
+    latest.use_ids(&mut request);
+
+    let download = server.post("/download", &serde_json::to_value(&request).unwrap());
+    assert_eq!(download.status_code, 200);
+    let json = download.json();
+    assert_eq!(json["delta"], expected);
+    assert_eq!(json["downloadlink"], "https://example.com/dev.tar.gz", "the full package stays the fallback");
+}
+
+#[test]
+fn failed_delta_switches_to_the_full_package() {
+    let server = TestServer::with_catalog(DELTA_CATALOG);
+    let mut request = client_on("1.1.5");
+    let download = server.start_download(&mut request);
+    assert!(download.json()["delta"].is_object());
+
+    // a failed download of the full package keeps the patch
+    let retry = server.post("/status", &delta_status(&request, false));
+    assert_eq!(retry.status_code, 200);
+    assert!(retry.json()["delta"].is_object());
+    retry.use_ids(&mut request);
+
+    let retry = server.post("/status", &delta_status(&request, true));
+    assert_eq!(retry.status_code, 200);
+    let json = retry.json();
+    assert_eq!(json["status"], "ok");
+    assert!(json.get("delta").is_none(), "{json}");
+    assert_eq!(json["downloadlink"], "https://example.com/dev.tar.gz");
+    retry.use_ids(&mut request);
+
+    // and the session stays on the full package
+    let retry = server.post("/status", &delta_status(&request, false));
+    assert!(retry.json().get("delta").is_none());
+}
+
+#[test]
+fn no_delta_from_other_versions() {
+    let server = TestServer::with_catalog(DELTA_CATALOG);
+    // 1.0.0 has no patch, the 1.1.0 patch is for Windows and this client runs Linux
+    for version in ["1.0.0", "1.1.0", ""] {
+        let mut request = client_on(version);
+        let latest = server.post("/latest", &serde_json::to_value(&request).unwrap());
+        let json = latest.json();
+        assert_eq!(json["status"], "ok", "{version}");
+        assert!(json.get("delta").is_none(), "{version}: {json}");
+
+        latest.use_ids(&mut request);
+        let json = server.post("/download", &serde_json::to_value(&request).unwrap()).json();
+        assert!(json.get("delta").is_none(), "{version}: {json}");
+    }
+
+    let mut request = client_on("1.1.0");
+    request.os.platform = String::from("Windows");
+    let json = server.post("/latest", &serde_json::to_value(&request).unwrap()).json();
+    assert_eq!(json["delta"]["url"], "https://example.com/dev-from-1.1.0.patch");
+}
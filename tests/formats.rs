@@ -0,0 +1,82 @@
+// Responses are encoded in the format the client asks for, by acceptformat or its Accept header
+mod common;
+
+use common::{client_request, TestResponse, TestServer};
+use updateserver::Channel;
+
+const LATEST:&str = "/latest?updater=hypertrail&os.platform=Linux&os.arch=x86&channel=Dev";
+
+fn assert_xml(response:&TestResponse) {
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.header("Content-Type").as_deref(), Some("application/xml"));
+    assert!(response.body.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?><latest>"#), "{}", response.body);
+    assert!(response.body.ends_with("</latest>"), "{}", response.body);
+    assert!(response.body.contains("<status>ok</status><version>1.2.0, [\"https://example.com/dev.tar.gz\"]</version>"), "{}", response.body);
+    assert!(response.body.contains("<info>update to 1.2.0.0</info>"), "{}", response.body);
+}
+
+fn assert_json(response:&TestResponse) {
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.header("Content-Type").as_deref(), Some("application/json"));
+    assert_eq!(response.json()["info"], "update to 1.2.0.0");
+}
+
+#[test]
+fn xml_by_acceptformat() {
+    let server = TestServer::start();
+    assert_xml(&server.send("GET", &format!("{LATEST}&acceptformat=xml"), ""));
+
+    let mut request = client_request(Channel::Dev);
+    request.acceptformat = String::from("XML");
+    assert_xml(&server.post("/latest", &serde_json::to_value(&request).unwrap()));
+
+    // acceptformat wins over the Accept header
+    assert_xml(&server.send_with_headers("GET", &format!("{LATEST}&acceptformat=xml"), &[("Accept", "application/json")], ""));
+}
+
+#[test]
+fn accept_header_with_quality_values() {
+    let server = TestServer::start();
+    let latest = |accept:&str| server.send_with_headers("GET", LATEST, &[("Accept", accept)], "");
+
+    assert_xml(&latest("application/json;q=0.5, application/xml"));
+    assert_xml(&latest("text/xml"));
+    assert_json(&latest("application/xml;q=0.2, application/json;q=0.8"));
+    assert_json(&latest("text/html, application/json;q=0.1"));
+
+    let response = latest("application/xml;q=0, text/html");
+    assert_eq!(response.status_code, 406);
+}
+
+#[test]
+fn anything_is_json() {
+    let server = TestServer::start();
+    assert_json(&server.send_with_headers("GET", LATEST, &[("Accept", "*/*")], ""));
+    assert_json(&server.send_with_headers("GET", LATEST, &[("Accept", "text/html;q=0.9, */*;q=0.8")], ""));
+    assert_json(&server.send("GET", LATEST, ""));
+}
+
+// The items of the response's Vary headers, the test fails when there is more than one header
+fn vary(response:&TestResponse) -> Vec<String> {
+    let headers = response.headers.lines().filter(|line| line.to_ascii_lowercase().starts_with("vary:")).collect::<Vec<&str>>();
+    assert!(headers.len() <= 1, "{headers:?}");
+    response.header("Vary").map(|vary| vary.split(',').map(|item| item.trim().to_string()).collect()).unwrap_or_default()
+}
+
+#[test]
+fn format_from_the_accept_header_varies_by_accept() {
+    let server = TestServer::start();
+    let by_accept = server.send_with_headers("GET", LATEST, &[("Accept", "application/xml"), ("Accept-Encoding", "gzip")], "");
+    assert_xml(&by_accept);
+    assert_eq!(vary(&by_accept), ["Accept", "Accept-Encoding"]);
+    // without an Accept header the JSON default still depends on it
+    assert_eq!(vary(&server.send("GET", LATEST, "")), ["Accept", "Accept-Encoding"]);
+    assert!(vary(&server.send_with_headers("GET", LATEST, &[("Accept", "text/html")], "")).contains(&String::from("Accept")));
+
+    // acceptformat alone picks the format
+    let by_acceptformat = server.send_with_headers("GET", &format!("{LATEST}&acceptformat=xml"), &[("Accept", "application/json")], "");
+    assert_xml(&by_acceptformat);
+    assert_eq!(vary(&by_acceptformat), ["Accept-Encoding"]);
+    let request = serde_json::to_value(client_request(Channel::Dev)).unwrap();
+    assert!(!vary(&server.post("/status", &request)).contains(&String::from("Accept")));
+}
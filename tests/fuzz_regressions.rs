@@ -0,0 +1,96 @@
+// Inputs from the fuzz targets in fuzz/ that crashed or misparsed, kept so they stay fixed
+mod common;
+
+use common::{client_request, TestServer};
+use updateserver::Channel;
+use updateserver::http::{self, Head, RequestBody, MAX_BODY_SIZE, MAX_HEADERS, MAX_HEAD_SIZE};
+
+// The body buffer was allocated from Content-Length up front, u64::MAX panicked with a
+// capacity overflow and took the server down
+#[test]
+fn content_length_beyond_memory() {
+    let mut reader = &b"POST /latest HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n{}"[..];
+    let raw = http::read_request(&mut reader).unwrap().unwrap();
+    assert!(matches!(raw.body, RequestBody::TooLarge(u64::MAX)));
+
+    let server = TestServer::start();
+    let response = server.send_raw(b"POST /latest HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n{}");
+    assert_eq!(response.status_code, 413);
+    assert_eq!(response.header("Connection").as_deref(), Some("close"));
+    let response = server.post("/latest", &serde_json::to_value(client_request(Channel::Dev)).unwrap());
+    assert_eq!(response.status_code, 200);
+}
+
+#[test]
+fn content_length_at_the_limit() {
+    let request = format!("POST /latest HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_SIZE + 1);
+    let raw = http::read_request(&mut request.as_bytes()).unwrap().unwrap();
+    assert!(matches!(raw.body, RequestBody::TooLarge(_)));
+
+    let body = " ".repeat(MAX_BODY_SIZE as usize);
+    let request = format!("POST /latest HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}", body.len());
+    let raw = http::read_request(&mut request.as_bytes()).unwrap().unwrap();
+    assert!(matches!(raw.body, RequestBody::Complete(read) if read.len() == body.len()));
+}
+
+// Header names are case-insensitive, a lowercase content-length left the body on the
+// connection where it was read as the next request line
+#[test]
+fn lowercase_content_length() {
+    let mut reader = &b"POST /latest HTTP/1.1\r\ncontent-length: 2\r\n\r\n{}GET / HTTP/1.1\r\n\r\n"[..];
+    let raw = http::read_request(&mut reader).unwrap().unwrap();
+    assert!(matches!(raw.body, RequestBody::Complete(body) if body == "{}"));
+    let next = http::read_request(&mut reader).unwrap().unwrap();
+    assert_eq!(next.request_line, "GET / HTTP/1.1\r\n");
+    assert!(http::read_request(&mut reader).unwrap().is_none());
+}
+
+#[test]
+fn head_without_end() {
+    let mut reader = &b"GET /latest HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n"[..];
+    let raw = http::read_request(&mut reader).unwrap().unwrap();
+    assert!(raw.head == Head::Truncated);
+    assert!(matches!(raw.body, RequestBody::Truncated));
+
+    // the missing end isn't taken for an empty body
+    let mut reader = &b"GET /latest HTTP/1.1\r\nHost: a\r\n"[..];
+    let raw = http::read_request(&mut reader).unwrap().unwrap();
+    assert!(raw.head == Head::Truncated);
+    assert!(matches!(raw.body, RequestBody::Truncated));
+}
+
+#[test]
+fn request_line_that_is_not_utf8() {
+    let mut reader = &[0xc3, 0x28, b' ', b'/', b' ', 0xff, b'\r', b'\n', b'\r', b'\n'][..];
+    let raw = http::read_request(&mut reader).unwrap().unwrap();
+    assert!(raw.head == Head::Complete);
+    assert!(http::HttpRequest::new(&raw.request_line, raw.headers, String::new()).is_some());
+}
+
+// A request line or header without an end was read into memory for as long as the client
+// kept sending
+#[test]
+fn head_without_a_limit() {
+    let request = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_HEAD_SIZE));
+    let raw = http::read_request(&mut request.as_bytes()).unwrap().unwrap();
+    assert!(raw.head == Head::TooLarge);
+    assert_eq!(raw.request_line.len(), MAX_HEAD_SIZE);
+
+    let request = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(MAX_HEAD_SIZE));
+    let raw = http::read_request(&mut request.as_bytes()).unwrap().unwrap();
+    assert!(raw.head == Head::TooLarge);
+
+    let request = format!("GET / HTTP/1.1\r\n{}\r\n", "X: 1\r\n".repeat(MAX_HEADERS + 1));
+    let raw = http::read_request(&mut request.as_bytes()).unwrap().unwrap();
+    assert!(raw.head == Head::TooLarge);
+    let request = format!("GET / HTTP/1.1\r\n{}\r\n", "X: 1\r\n".repeat(MAX_HEADERS));
+    let raw = http::read_request(&mut request.as_bytes()).unwrap().unwrap();
+    assert!(raw.head == Head::Complete);
+
+    let server = TestServer::start();
+    let response = server.send_raw(format!("GET /latest HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(MAX_HEAD_SIZE)).as_bytes());
+    assert_eq!(response.status_code, 431);
+    assert_eq!(response.header("Connection").as_deref(), Some("close"));
+    let response = server.post("/latest", &serde_json::to_value(client_request(Channel::Dev)).unwrap());
+    assert_eq!(response.status_code, 200);
+}
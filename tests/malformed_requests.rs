@@ -0,0 +1,157 @@
+// Requests the server has to turn away without losing the connection or its state
+mod common;
+
+use common::{client_request, TestServer};
+use updateserver::Channel;
+
+#[test]
+fn body_that_is_not_json() {
+    let server = TestServer::start();
+    for endpoint in ["/latest", "/download", "/status"] {
+        let response = server.send("POST", endpoint, "{\"updater\": ");
+        assert_eq!(response.status_code, 400, "{endpoint}");
+        assert!(response.body.starts_with("invalid request body"), "{endpoint}: {}", response.body);
+    }
+}
+
+#[test]
+fn body_with_missing_fields() {
+    let server = TestServer::start();
+    let response = server.send("POST", "/latest", r#"{"updater":"hypertrail"}"#);
+    assert_eq!(response.status_code, 400);
+    assert!(response.body.contains("missing field"), "{}", response.body);
+
+    let response = server.send("POST", "/status", r#"{"eventtype":"Download","action":"retry","result":1}"#);
+    assert_eq!(response.status_code, 400);
+    assert!(response.body.contains("missing field `request`"), "{}", response.body);
+}
+
+#[test]
+fn body_with_wrong_types() {
+    let server = TestServer::start();
+    let mut request = serde_json::to_value(client_request(Channel::Dev)).unwrap();
+    request["channel"] = serde_json::json!("Nightly");
+    assert_eq!(server.post("/latest", &request).status_code, 400);
+
+    let mut request = serde_json::to_value(client_request(Channel::Dev)).unwrap();
+    request["protocol"] = serde_json::json!("one");
+    assert_eq!(server.post("/download", &request).status_code, 400);
+
+    let response = server.send("POST", "/status", "[1, 2, 3]");
+    assert_eq!(response.status_code, 400);
+}
+
+#[test]
+fn query_that_does_not_parse() {
+    let server = TestServer::start();
+    let response = server.send("GET", "/latest?protocol=abc", "");
+    assert_eq!(response.status_code, 400);
+    assert!(response.body.starts_with("invalid query"), "{}", response.body);
+}
+
+#[test]
+fn unsupported_response_format() {
+    let server = TestServer::start();
+    let mut request = client_request(Channel::Dev);
+    request.acceptformat = String::from("yaml");
+    let response = server.post("/latest", &serde_json::to_value(&request).unwrap());
+    assert_eq!(response.status_code, 406);
+}
+
+#[test]
+fn malformed_request_line() {
+    let server = TestServer::start();
+    let response = server.send_raw(b"NONSENSE\r\n\r\n");
+    assert_eq!(response.status_code, 400);
+    assert_eq!(response.body, "malformed request line");
+    assert_eq!(response.header("Connection").as_deref(), Some("close"));
+
+    let response = server.send_raw(b"GET /latest HTTP/1.1 extra\r\n\r\n");
+    assert_eq!(response.status_code, 400);
+}
+
+#[test]
+fn unknown_path_and_wrong_method() {
+    let server = TestServer::start();
+    assert_eq!(server.send("GET", "/nothing/here", "").status_code, 404);
+
+    let response = server.send("DELETE", "/latest", "");
+    assert_eq!(response.status_code, 405);
+    assert_eq!(response.header("Allow").as_deref(), Some("GET, POST"));
+
+    let response = server.send("GET", "/service/update2", "");
+    assert_eq!(response.status_code, 405);
+    assert_eq!(response.header("Allow").as_deref(), Some("POST"));
+}
+
+#[test]
+fn body_shorter_than_its_content_length() {
+    let server = TestServer::start();
+    let response = server.send_raw_then_close(b"POST /latest HTTP/1.1\r\nContent-Length: 500\r\n\r\n{\"updater\":");
+    assert_eq!(response.status_code, 400);
+    assert_eq!(response.body, "incomplete request body");
+    assert_eq!(response.header("Connection").as_deref(), Some("close"));
+
+    // the rest never comes
+    let response = server.send_raw(b"POST /latest HTTP/1.1\r\nContent-Length: 500\r\n\r\n{\"updater\":");
+    assert_eq!(response.status_code, 408);
+    assert_eq!(response.body, "request body not received in time");
+    assert_eq!(response.header("Connection").as_deref(), Some("close"));
+}
+
+#[test]
+fn server_keeps_serving_after_bad_requests() {
+    let server = TestServer::start();
+    server.send_raw(b"\r\n\r\n");
+    server.send_raw(&[0xff, 0xfe, 0x00, b'\r', b'\n', b'\r', b'\n']);
+    server.send("POST", "/status", "not json");
+    let response = server.post("/latest", &serde_json::to_value(client_request(Channel::Dev)).unwrap());
+    assert_eq!(response.status_code, 200);
+}
+
+// A Content-Length the server and a proxy in front could read differently ends the
+// connection, the bytes after the head are never taken as the next request
+#[test]
+fn content_length_that_is_not_one_number() {
+    let server = TestServer::start();
+    for length in ["abc", "+2", "-1", "2, 2", "", "99999999999999999999999"] {
+        let request = format!("POST /latest HTTP/1.1\r\nContent-Length: {length}\r\n\r\n{{}}GET /publickey HTTP/1.1\r\n\r\n");
+        let response = server.send_raw(request.as_bytes());
+        assert_eq!(response.status_code, 400, "{length:?}");
+        assert_eq!(response.header("Connection").as_deref(), Some("close"), "{length:?}");
+        assert_eq!(response.body, "invalid Content-Length", "{length:?}");
+    }
+    let response = server.send_raw(b"POST /latest HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 2\r\n\r\n{}GET /publickey HTTP/1.1\r\n\r\n");
+    assert_eq!(response.status_code, 400);
+    assert_eq!(response.body, "invalid Content-Length");
+}
+
+#[test]
+fn transfer_encoding_is_not_implemented() {
+    let server = TestServer::start();
+    for transfer_encoding in ["chunked", "gzip, chunked", "identity"] {
+        let request = format!("POST /latest HTTP/1.1\r\nTransfer-Encoding: {transfer_encoding}\r\nContent-Length: 5\r\n\r\n2\r\n{{}}\r\n0\r\n\r\n");
+        let response = server.send_raw(request.as_bytes());
+        assert_eq!(response.status_code, 501, "{transfer_encoding}");
+        assert_eq!(response.header("Connection").as_deref(), Some("close"), "{transfer_encoding}");
+        assert_eq!(response.body, "Transfer-Encoding is not supported");
+    }
+    let response = server.post("/latest", &serde_json::to_value(client_request(Channel::Dev)).unwrap());
+    assert_eq!(response.status_code, 200);
+}
+
+// A head without its empty line isn't routed: it's cut off, or the client stopped sending it
+#[test]
+fn head_without_its_end() {
+    let server = TestServer::start();
+    let response = server.send_raw_then_close(b"GET /latest?updater=hypertrail&os.platform=Linux&os.arch=x86 HTTP/1.1\r\nHost: a\r\n");
+    assert_eq!(response.status_code, 400);
+    assert_eq!(response.body, "incomplete request head");
+    assert_eq!(response.header("Connection").as_deref(), Some("close"));
+
+    // the test server's idle timeout is a second
+    let response = server.send_raw(b"GET /latest?updater=hypertrail&os.platform=Linux&os.arch=x86 HTTP/1.1\r\nHost: a\r\n");
+    assert_eq!(response.status_code, 408);
+    assert_eq!(response.body, "request head not received in time");
+    assert_eq!(response.header("Connection").as_deref(), Some("close"));
+}
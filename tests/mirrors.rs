@@ -0,0 +1,68 @@
+// Mirror reports in /status: only a session that was offered a mirror can demote it
+mod common;
+
+use common::{client_request, status_request, TestServer};
+use serde_json::Value;
+use updateserver::{Action, Channel};
+
+const MIRRORS_CATALOG:&str = r#"{
+    "stable":[], "beta":[], "canary":[], "extended":[],
+    "dev":[{"major":1,"minor":2,"build":0,"patch":0,"count":0,"urls":["https://a.example.com/dev.tar.gz","https://b.example.com/dev.tar.gz"]}]
+}"#;
+
+fn mirrors(json:&Value) -> Vec<String> {
+    json["mirrors"].as_array().unwrap().iter().map(|mirror| mirror.as_str().unwrap().to_string()).collect()
+}
+
+// Equal weights take turns going first, a demoted mirror never does
+fn first_mirrors(server:&TestServer) -> Vec<String> {
+    (0..2).map(|_| {
+        let download = server.start_download(&mut client_request(Channel::Dev));
+        assert_eq!(download.status_code, 200, "{}", download.body);
+        mirrors(&download.json())[0].clone()
+    }).collect()
+}
+
+fn report(server:&TestServer, request:&updateserver::Request, mirror:&str) -> Value {
+    let mut status = status_request(request, Action::retry, 0);
+    status["mirror"] = Value::from(mirror);
+    let response = server.post("/status", &status);
+    let json = response.json();
+    assert_eq!(response.status_code, if json["status"] == "errorinvalidsession" { 404 } else { 200 });
+    json
+}
+
+#[test]
+fn reports_without_a_session_are_ignored() {
+    let server = TestServer::with_catalog(MIRRORS_CATALOG);
+    let mut request = client_request(Channel::Dev);
+    request.sessionid = String::from("made-up");
+    request.requestid = String::from("made-up");
+    let json = report(&server, &request, "https://a.example.com/dev.tar.gz");
+    assert_eq!(json["status"], "errorinvalidsession");
+
+    let mut first = first_mirrors(&server);
+    first.sort();
+    assert_eq!(first, ["https://a.example.com/dev.tar.gz", "https://b.example.com/dev.tar.gz"]);
+}
+
+#[test]
+fn only_offered_mirrors_can_be_reported() {
+    let server = TestServer::with_catalog(MIRRORS_CATALOG);
+    let mut request = client_request(Channel::Dev);
+    let download = server.start_download(&mut request);
+    let offered = mirrors(&download.json());
+
+    let retry = report(&server, &request, "https://elsewhere.example.com/dev.tar.gz");
+    assert_eq!(retry["status"], "ok");
+    assert!(!mirrors(&retry).contains(&String::from("https://elsewhere.example.com/dev.tar.gz")));
+    let mut first = first_mirrors(&server);
+    first.sort();
+    assert_eq!(first, ["https://a.example.com/dev.tar.gz", "https://b.example.com/dev.tar.gz"]);
+
+    // the retry comes back with the failing mirror last
+    let retry = report(&server, &request, &offered[0]);
+    assert_eq!(retry["status"], "ok");
+    assert_eq!(mirrors(&retry).last(), Some(&offered[0]));
+    assert!(first_mirrors(&server).iter().all(|first| *first != offered[0]));
+}
@@ -0,0 +1,174 @@
+// The Omaha v3 XML and Omaha 4 JSON endpoints, as the stock Chromium updater talks to them
+mod common;
+
+use common::{TestServer, CATALOG, COMPONENT, PRODUCT};
+
+// An update check for each app id, from a Linux x86 client on the Dev channel
+fn update_check(appids:&[&str]) -> String {
+    update_check_on(appids, "dev")
+}
+
+fn update_check_on(appids:&[&str], ap:&str) -> String {
+    let apps = appids
+        .iter()
+        .map(|appid| format!(r#"<app appid="{appid}" version="1.0.0.0" ap="{ap}"><updatecheck/></app>"#))
+        .collect::<String>();
+    omaha_request(&apps)
+}
+
+// An event of one app in the same session as the update checks above
+fn event(appid:&str, eventtype:i32, eventresult:i32) -> String {
+    omaha_request(&format!(r#"<app appid="{appid}" version="1.0.0.0" ap="dev"><event eventtype="{eventtype}" eventresult="{eventresult}"/></app>"#))
+}
+
+fn omaha_request(apps:&str) -> String {
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?><request protocol="3.0" updater="Omaha" updaterversion="1.3.36.1" sessionid="{{session}}" requestid="{{request}}"><os platform="Linux" version="6.1" arch="x86"/>{apps}</request>"#)
+}
+
+// The status of every app's update check, in request order
+fn update_statuses(server:&TestServer, appids:&[&str]) -> Vec<String> {
+    let response = server.send("POST", "/service/update2", &update_check(appids));
+    assert_eq!(response.status_code, 200);
+    appids.iter().map(|appid| {
+        let app = app_element(&response.body, appid);
+        let marker = "<updatecheck status=\"";
+        let start = app.find(marker).unwrap_or_else(|| panic!("no update check in {app}")) + marker.len();
+        app[start..start + app[start..].find('"').unwrap()].to_string()
+    }).collect()
+}
+
+// The <app> element of an app id in an XML response
+fn app_element<'a>(body:&'a str, appid:&str) -> &'a str {
+    let start = body.find(&format!(r#"<app appid="{appid}""#)).unwrap_or_else(|| panic!("no app {appid} in {body}"));
+    let end = body[start..].find("</app>").map_or(body.len(), |end| start + end);
+    &body[start..end]
+}
+
+#[test]
+fn product_app_id_gets_the_product() {
+    let server = TestServer::start();
+    let response = server.send("POST", "/service/update2", &update_check(&[PRODUCT]));
+    assert_eq!(response.status_code, 200);
+    let app = app_element(&response.body, PRODUCT);
+    assert!(app.contains(r#"status="ok""#), "{app}");
+    assert!(app.contains(r#"<url codebase="https://example.com/"/>"#), "{app}");
+    assert!(app.contains(r#"<manifest version="1.2.0.0">"#), "{app}");
+}
+
+#[test]
+fn unknown_app_id_gets_no_package() {
+    let server = TestServer::start();
+    let stray = "{00000000-0000-0000-0000-000000000000}";
+    let response = server.send("POST", "/service/update2", &update_check(&[PRODUCT, stray]));
+    assert_eq!(response.status_code, 200);
+    let app = app_element(&response.body, stray);
+    assert!(app.contains(r#"status="error-unknownApplication""#), "{app}");
+    assert!(!app.contains("<url"), "{app}");
+    assert!(app_element(&response.body, PRODUCT).contains("<url"));
+}
+
+#[test]
+fn unknown_product_is_not_served_the_default() {
+    let server = TestServer::start();
+    let body = update_check(&["otherbrowser"]).replace(r#"updater="Omaha""#, r#"updater="otherbrowser""#);
+    let response = server.send("POST", "/service/update2", &body);
+    assert_eq!(response.status_code, 200);
+    let app = app_element(&response.body, "otherbrowser");
+    assert!(app.contains(r#"status="error-unknownApplication""#), "{app}");
+    assert!(!app.contains("<updatecheck"), "{app}");
+}
+
+#[test]
+fn every_app_gets_its_own_session() {
+    let server = TestServer::start();
+    let response = server.send("POST", "/service/update2", &update_check(&[PRODUCT, COMPONENT]));
+    assert_eq!(response.status_code, 200);
+    assert!(app_element(&response.body, PRODUCT).contains(r#"<updatecheck status="ok""#), "{}", response.body);
+    let component = app_element(&response.body, COMPONENT);
+    assert!(component.contains(r#"<updatecheck status="ok""#), "{component}");
+    assert!(component.contains(r#"<package name="widevine.zip" required="true" size="2048" hash_sha256="abcd"/>"#), "{component}");
+
+    // both sessions exist, a second update check in the same session is refused for each app
+    let response = server.send("POST", "/service/update2", &update_check(&[PRODUCT, COMPONENT]));
+    for appid in [PRODUCT, COMPONENT] {
+        let app = app_element(&response.body, appid);
+        assert!(app.contains(r#"<updatecheck status="error-internal" info="update session already exists""#), "{app}");
+    }
+}
+
+#[test]
+fn version_without_a_package_is_no_update() {
+    let server = TestServer::start();
+    let response = server.send("POST", "/service/update2", &update_check_on(&[COMPONENT], "beta"));
+    let app = app_element(&response.body, COMPONENT);
+    assert!(app.contains(r#"<updatecheck status="noupdate" info="4.9.0.0 has no package for this client""#), "{app}");
+    assert!(!app.contains("<url"), "{app}");
+}
+
+// An open session refuses another update check, so the status of the next one tells whether
+// the event ended the session
+#[test]
+fn events_go_through_status_handling() {
+    let server = TestServer::start();
+    assert_eq!(update_statuses(&server, &[PRODUCT]), ["ok"]);
+
+    // a download or install error keeps the session for a retry
+    let response = server.send("POST", "/service/update2", &event(PRODUCT, 3, 0));
+    assert!(app_element(&response.body, PRODUCT).contains(r#"<event status="ok"/>"#), "{}", response.body);
+    assert_eq!(update_statuses(&server, &[PRODUCT]), ["error-internal"]);
+    // progress reports don't end it either
+    server.send("POST", "/service/update2", &event(PRODUCT, 14, 1));
+    assert_eq!(update_statuses(&server, &[PRODUCT]), ["error-internal"]);
+
+    // a completed update does
+    server.send("POST", "/service/update2", &event(PRODUCT, 3, 1));
+    assert_eq!(update_statuses(&server, &[PRODUCT]), ["ok"]);
+    // and so does a cancellation
+    server.send("POST", "/service/update2", &event(PRODUCT, 3, 4));
+    assert_eq!(update_statuses(&server, &[PRODUCT]), ["ok"]);
+}
+
+#[test]
+fn events_only_end_their_own_app() {
+    let server = TestServer::start();
+    assert_eq!(update_statuses(&server, &[PRODUCT, COMPONENT]), ["ok", "ok"]);
+    server.send("POST", "/service/update2", &event(PRODUCT, 3, 1));
+    assert_eq!(update_statuses(&server, &[PRODUCT, COMPONENT]), ["ok", "error-internal"]);
+}
+
+#[test]
+fn json_download_carries_size_and_hash() {
+    let server = TestServer::start();
+    let body = format!(r#"{{"request":{{"protocol":"4.0","@updater":"Omaha","sessionid":"{{json}}","os":{{"platform":"Linux","arch":"x86"}},"apps":[{{"appid":"{COMPONENT}","version":"1.0.0.0","ap":"dev","updatecheck":{{}}}}]}}}}"#);
+    let response = server.send("POST", "/service/update2/json", &body);
+    assert_eq!(response.status_code, 200, "{}", response.body);
+    let json:serde_json::Value = serde_json::from_str(response.body.trim_start_matches(")]}'\n")).unwrap();
+    let operation = &json["response"]["apps"][0]["updatecheck"]["pipelines"][0]["operations"][0];
+    assert_eq!(operation["type"], "download");
+    assert_eq!(operation["urls"][0]["url"], "https://example.com/widevine.zip");
+    assert_eq!(operation["size"], 2048);
+    assert_eq!(operation["out"]["sha256"], "abcd");
+}
+
+// dedup="cr" is a counting mode every Chromium client sends, it must not put them all in one bucket
+#[test]
+fn rollout_buckets_omaha_clients_by_their_ids() {
+    let catalog = CATALOG.replacen(r#""urls":["https://example.com/dev.tar.gz"]"#, r#""rollout":50,"urls":["https://example.com/dev.tar.gz"]"#, 1);
+    let server = TestServer::with_catalog(&catalog);
+    // whether the half rolled out 1.2.0.0 is offered, each check in a session of its own
+    let offered = |session:usize, request_ids:&str, app_ids:&str| {
+        let body = format!(r#"<request protocol="3.0" updater="Omaha" updaterversion="1.3.36.1" sessionid="{{session-{session}}}" dedup="cr" {request_ids}><os platform="Linux" version="6.1" arch="x86"/><app appid="{PRODUCT}" version="1.0.0.0" ap="dev" {app_ids}><updatecheck/></app></request>"#);
+        let response = server.send("POST", "/service/update2", &body);
+        app_element(&response.body, PRODUCT).contains(r#"<manifest version="1.2.0.0">"#)
+    };
+
+    // without iid or userid a client has no stable id and only gets fully rolled out versions
+    assert!(!offered(0, "", ""));
+    assert!(!offered(1, "", ""));
+
+    let by_iid = (0..20).map(|index| offered(index + 2, "", &format!(r#"iid="{{install-{index}}}""#))).collect::<Vec<_>>();
+    assert!(by_iid.contains(&true) && by_iid.contains(&false), "{by_iid:?}");
+    // the request's userid stands in for a missing iid
+    let by_userid = (0..20).map(|index| offered(index + 22, &format!(r#"userid="{{user-{index}}}""#), "")).collect::<Vec<_>>();
+    assert!(by_userid.contains(&true) && by_userid.contains(&false), "{by_userid:?}");
+}
@@ -0,0 +1,56 @@
+// Signed update responses: the key comes with the server's configuration
+mod common;
+
+use common::{client_request, TestServer, CATALOG};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ed25519_dalek::{Signature, SigningKey, Verifier, VerifyingKey};
+use updateserver::{Channel, ServerConfig};
+
+fn signed_server(seed:u8) -> TestServer {
+    TestServer::with_config(CATALOG, ServerConfig{ signing_key:Some(SigningKey::from_bytes(&[seed; 32])), ..Default::default() })
+}
+
+#[test]
+fn servers_keep_their_own_keys() {
+    let first = signed_server(1);
+    let second = signed_server(2);
+    let unsigned = TestServer::start();
+
+    let first_key = first.send("GET", "/publickey", "").json();
+    let second_key = second.send("GET", "/publickey", "").json();
+    assert_eq!(first_key["algorithm"], "ed25519");
+    assert_ne!(first_key["publickey"], second_key["publickey"]);
+    assert_eq!(unsigned.send("GET", "/publickey", "").status_code, 404);
+
+    let request = serde_json::to_value(client_request(Channel::Dev)).unwrap();
+    let signature = first.post("/latest", &request).header("X-Signature").unwrap();
+    assert!(signature.starts_with(&format!("keyid={}; ed25519=", first_key["keyid"].as_str().unwrap())), "{signature}");
+    assert!(unsigned.post("/latest", &request).header("X-Signature").is_none());
+}
+
+// What a client does: pin /publickey, then check X-Signature over the exact body before trusting
+// the package hash in it
+#[test]
+fn download_signature_covers_the_package_hash() {
+    let server = signed_server(3);
+    let public_key = server.send("GET", "/publickey", "").json();
+    let public_key:[u8; 32] = BASE64.decode(public_key["publickey"].as_str().unwrap()).unwrap().try_into().unwrap();
+    let public_key = VerifyingKey::from_bytes(&public_key).unwrap();
+
+    let mut request = client_request(Channel::Stable);
+    let download = server.start_download(&mut request);
+    assert_eq!(download.status_code, 200, "{}", download.body);
+    let json = download.json();
+    assert_eq!(json["downloadlink"], "https://example.com/stable.tar.gz");
+    assert_eq!(json["sha256"], "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08");
+    assert_eq!(json["size"], 4);
+
+    let header = download.header("X-Signature").unwrap();
+    let signature = header.split("ed25519=").nth(1).unwrap();
+    let signature = Signature::from_slice(&BASE64.decode(signature).unwrap()).unwrap();
+    public_key.verify(download.body.as_bytes(), &signature).unwrap();
+
+    let tampered = download.body.replace("9f86d081", "00000000");
+    assert!(public_key.verify(tampered.as_bytes(), &signature).is_err());
+}
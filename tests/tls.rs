@@ -0,0 +1,137 @@
+// HTTPS with a self-signed certificate, and the plain HTTP port that redirects to it
+mod common;
+
+use std::fs;
+use std::io::{prelude::*, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use rustls::pki_types::{CertificateDer, ServerName};
+use updateserver::{catalog, tls, Server};
+use updateserver::connection::KeepAlive;
+
+// A server behind TlsAcceptor with a fresh certificate for localhost, returns its port and the
+// certificate to trust
+fn start_tls_server() -> (u16, CertificateDer<'static>) {
+    let dir = std::env::temp_dir().join(format!("updateserver-tls-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let certified = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+    fs::write(&cert_path, certified.cert.pem()).unwrap();
+    fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+    let versions_path = dir.join("versions.json");
+    fs::write(&versions_path, common::CATALOG).unwrap();
+    let products = catalog::load_products(
+        &dir.join("products.json").to_string_lossy(),
+        &versions_path.to_string_lossy(),
+        &dir.join("catalogs").to_string_lossy()
+    ).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let settings = tls::TlsSettings{
+        cert_path:cert_path.to_string_lossy().to_string(),
+        key_path:key_path.to_string_lossy().to_string(),
+        https_addr:format!("127.0.0.1:{port}"),
+        redirect:false
+    };
+    let mut acceptor = tls::TlsAcceptor::new(settings).unwrap();
+    thread::spawn(move || {
+        let keep_alive = KeepAlive{ idle_timeout:Duration::from_secs(1), max_requests:10, request_timeout:Duration::from_secs(2) };
+        Arc::new(Server::new(products, Default::default())).serve(listener, |stream| acceptor.accept(stream), keep_alive, 16);
+    });
+    (port, certified.cert.der().clone())
+}
+
+#[test]
+fn request_over_tls() {
+    let (port, cert) = start_tls_server();
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).unwrap();
+    let config = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+    let connection = ClientConnection::new(Arc::new(config), ServerName::try_from("localhost").unwrap()).unwrap();
+    let socket = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut stream = StreamOwned::new(connection, socket);
+
+    let request = "GET /latest?updater=hypertrail&channel=Stable&os.platform=Linux&os.arch=x86&acceptformat=json HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.contains(r#""info":"update to 1.0.0.0""#), "{response}");
+}
+
+// A client that connects and sends nothing doesn't hold up the redirects of other clients
+#[test]
+fn silent_client_does_not_stop_redirects() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tls::spawn_redirect(listener, "127.0.0.1:7443");
+
+    let _silent = TcpStream::connect(addr).unwrap();
+    let started = Instant::now();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(15))).unwrap();
+    stream.write_all(b"GET /latest?channel=Stable HTTP/1.1\r\nHost: localhost:7778\r\n\r\n").unwrap();
+    let mut status_line = String::new();
+    let mut reader = BufReader::new(stream);
+    reader.read_line(&mut status_line).unwrap();
+    assert!(status_line.starts_with("HTTP/1.1 308"), "{status_line}");
+    assert!(started.elapsed() < Duration::from_secs(10));
+    let mut headers = String::new();
+    reader.read_to_string(&mut headers).unwrap();
+    assert!(headers.contains("Location: https://localhost:7443/latest?channel=Stable\r\n"), "{headers}");
+}
+
+// Sends the start of a request a byte at a time, one byte per interval
+fn drip(addr:std::net::SocketAddr, interval:Duration) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    thread::spawn(move || {
+        for byte in b"GET /latest?channel=Stable HTTP/1.1\r\nHost: localhost:7778\r\n".repeat(10) {
+            if writer.write_all(&[byte]).is_err() {
+                break;
+            }
+            thread::sleep(interval);
+        }
+    });
+    stream
+}
+
+#[test]
+fn slow_client_does_not_stop_redirects() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tls::spawn_redirect(listener, "127.0.0.1:7443");
+
+    let _slow = drip(addr, Duration::from_millis(100));
+    thread::sleep(Duration::from_millis(300));
+    let started = Instant::now();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(15))).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line).unwrap();
+    assert!(status_line.starts_with("HTTP/1.1 308"), "{status_line}");
+    assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+}
+
+// Sending the request slowly doesn't keep the connection open past the timeout
+#[test]
+fn slow_client_is_dropped() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tls::spawn_redirect(listener, "127.0.0.1:7443");
+
+    let started = Instant::now();
+    let mut slow = drip(addr, Duration::from_millis(200));
+    slow.set_read_timeout(Some(Duration::from_secs(15))).unwrap();
+    let mut response = vec![];
+    let _ = slow.read_to_end(&mut response);
+    assert!(response.is_empty(), "{}", String::from_utf8_lossy(&response));
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_secs(4) && elapsed < Duration::from_secs(8), "{elapsed:?}");
+}
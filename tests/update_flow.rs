@@ -0,0 +1,259 @@
+// The update protocol over HTTP: a session from /latest through /download to /status,
+// retries, abandoned sessions and sessions the server doesn't know.
+mod common;
+
+use common::{client_request, status_request, TestServer, COMPONENT};
+use updateserver::{Action, AppRequest, Channel};
+
+#[test]
+fn latest_download_status() {
+    let server = TestServer::start();
+    let mut request = client_request(Channel::Dev);
+
+    let latest = server.post("/latest", &serde_json::to_value(&request).unwrap());
+    assert_eq!(latest.status_code, 200);
+    let json = latest.json();
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["actions"], serde_json::json!(["download", "abandon"]));
+    assert_eq!(json["info"], "update to 1.2.0.0");
+    assert!(!json["sessionid"].as_str().unwrap().is_empty());
+    latest.use_ids(&mut request);
+    let latest_requestid = request.requestid.clone();
+
+    let download = server.post("/download", &serde_json::to_value(&request).unwrap());
+    assert_eq!(download.status_code, 200);
+    let json = download.json();
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["sessionid"], request.sessionid.as_str());
+    assert_ne!(json["requestid"], latest_requestid.as_str(), "every step gets a new request id");
+    assert_eq!(json["downloadlink"], "https://example.com/dev.tar.gz");
+    assert_eq!(json["actions"], serde_json::json!(["abandon", "retry"]));
+    download.use_ids(&mut request);
+
+    let status = server.post("/status", &status_request(&request, Action::retry, 1));
+    assert_eq!(status.status_code, 200);
+    let json = status.json();
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["sessionid"], request.sessionid.as_str());
+    assert_eq!(json["requestid"], request.requestid.as_str());
+
+    // a finished session is gone
+    let again = server.post("/status", &status_request(&request, Action::retry, 1));
+    assert_eq!(again.status_code, 404);
+    assert_eq!(again.json()["status"], "errorinvalidsession");
+}
+
+#[test]
+fn query_parameters_start_a_session() {
+    let server = TestServer::start();
+    let latest = server.send("GET", "/latest?updater=hypertrail&channel=Stable&os.platform=Linux&os.arch=x86&acceptformat=json", "");
+    assert_eq!(latest.status_code, 200);
+    assert_eq!(latest.json()["info"], "update to 1.0.0.0");
+}
+
+#[test]
+fn retry_after_failed_download() {
+    let server = TestServer::start();
+    let mut request = client_request(Channel::Dev);
+    let download = server.start_download(&mut request);
+    assert_eq!(download.status_code, 200);
+
+    let retry = server.post("/status", &status_request(&request, Action::retry, 0));
+    assert_eq!(retry.status_code, 200);
+    let json = retry.json();
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["downloadlink"], "https://example.com/dev.tar.gz");
+    assert_ne!(json["requestid"], request.requestid.as_str());
+    retry.use_ids(&mut request);
+
+    // the retried download can be retried again and then finished
+    let retry = server.post("/status", &status_request(&request, Action::retry, 2));
+    assert_eq!(retry.status_code, 200);
+    retry.use_ids(&mut request);
+    let status = server.post("/status", &status_request(&request, Action::retry, 1));
+    assert_eq!(status.json()["status"], "ok");
+}
+
+#[test]
+fn abandon_ends_the_session() {
+    let server = TestServer::start();
+    let mut request = client_request(Channel::Dev);
+    server.start_download(&mut request);
+
+    let abandon = server.post("/status", &status_request(&request, Action::abandon, 2));
+    assert_eq!(abandon.status_code, 200);
+    assert_eq!(abandon.json()["status"], "updateabandoned");
+
+    let download = server.post("/download", &serde_json::to_value(&request).unwrap());
+    assert_eq!(download.status_code, 200);
+    assert_eq!(download.json()["status"], "noupdate");
+
+    let status = server.post("/status", &status_request(&request, Action::abandon, 2));
+    assert_eq!(status.status_code, 404);
+    assert_eq!(status.json()["status"], "errorinvalidsession");
+}
+
+#[test]
+fn abandon_right_after_latest() {
+    let server = TestServer::start();
+    let mut request = client_request(Channel::Dev);
+    let latest = server.post("/latest", &serde_json::to_value(&request).unwrap());
+    latest.use_ids(&mut request);
+
+    let abandon = server.post("/status", &status_request(&request, Action::abandon, 0));
+    assert_eq!(abandon.status_code, 200);
+    assert_eq!(abandon.json()["status"], "updateabandoned");
+}
+
+#[test]
+fn unknown_session() {
+    let server = TestServer::start();
+    let mut request = client_request(Channel::Dev);
+    request.sessionid = String::from("nosuchsession");
+    request.requestid = String::from("nosuchrequest");
+
+    let status = server.post("/status", &status_request(&request, Action::retry, 0));
+    assert_eq!(status.status_code, 404);
+    let json = status.json();
+    assert_eq!(json["status"], "errorinvalidsession");
+    assert_eq!(json["sessionid"], "nosuchsession");
+    assert_eq!(json["requestid"], "nosuchrequest");
+
+    let download = server.post("/download", &serde_json::to_value(&request).unwrap());
+    assert_eq!(download.status_code, 200);
+    let json = download.json();
+    assert_eq!(json["status"], "noupdate");
+    assert_eq!(json["actions"], serde_json::json!([]));
+}
+
+#[test]
+fn action_the_session_does_not_allow() {
+    let server = TestServer::start();
+    let mut request = client_request(Channel::Dev);
+    server.start_download(&mut request);
+
+    // after a download only abandon and retry are allowed
+    let status = server.post("/status", &status_request(&request, Action::complete, 1));
+    assert_eq!(status.status_code, 404);
+    assert_eq!(status.json()["status"], "errorinvalidsession");
+}
+
+#[test]
+fn download_with_a_stale_request_id() {
+    let server = TestServer::start();
+    let mut request = client_request(Channel::Dev);
+    let latest = server.post("/latest", &serde_json::to_value(&request).unwrap());
+    latest.use_ids(&mut request);
+    request.requestid = String::from("stale");
+
+    let download = server.post("/download", &serde_json::to_value(&request).unwrap());
+    assert_eq!(download.status_code, 200);
+    assert_eq!(download.json()["status"], "noupdate");
+}
+
+// every answer echoes the ids, without them there's nothing to answer with
+#[test]
+fn requests_without_ids() {
+    let server = TestServer::start();
+    let request = client_request(Channel::Dev);
+    let status = server.post("/status", &status_request(&request, Action::retry, 1));
+    assert_eq!(status.status_code, 500);
+    let download = server.post("/download", &serde_json::to_value(&request).unwrap());
+    assert_eq!(download.status_code, 500);
+}
+
+#[test]
+fn unknown_product() {
+    let server = TestServer::start();
+    let mut request = client_request(Channel::Dev);
+    request.updater = String::from("someotherbrowser");
+    let latest = server.post("/latest", &serde_json::to_value(&request).unwrap());
+    assert_eq!(latest.status_code, 404);
+    let json = latest.json();
+    assert_eq!(json["status"], "errorunknownproduct");
+    assert_eq!(json["actions"], serde_json::json!([]));
+
+    // a rejected product gets ids to match the answer, not the 500 of a known product's request
+    let download = server.post("/download", &serde_json::to_value(&request).unwrap());
+    assert_eq!(download.status_code, 404);
+    assert_eq!(download.json()["status"], "errorunknownproduct");
+    assert!(!download.json()["sessionid"].as_str().unwrap().is_empty());
+    let status = server.post("/status", &status_request(&request, Action::retry, 1));
+    assert_eq!(status.status_code, 404);
+    assert_eq!(status.json()["status"], "errorunknownproduct");
+    assert!(!status.json()["requestid"].as_str().unwrap().is_empty());
+}
+
+#[test]
+fn unsupported_protocol_starts_no_session() {
+    let server = TestServer::start();
+    let mut request = client_request(Channel::Dev);
+    request.sessionid = String::from("old-client-session");
+    request.protocol = 0.5;
+    let latest = server.post("/latest", &serde_json::to_value(&request).unwrap());
+    assert_eq!(latest.status_code, 401);
+    assert_eq!(latest.json()["status"], "errorunsupportedprotocol");
+
+    // nothing was left behind, the same session id starts a session once the protocol is supported
+    request.protocol = 1.0;
+    let latest = server.post("/latest", &serde_json::to_value(&request).unwrap());
+    assert_eq!(latest.status_code, 200);
+    assert_eq!(latest.json()["status"], "ok");
+
+    // the protocol is checked before the session, a retry with an old protocol learns why it failed
+    request.protocol = 0.5;
+    let retry = server.post("/latest", &serde_json::to_value(&request).unwrap());
+    assert_eq!(retry.status_code, 401);
+    assert_eq!(retry.json()["status"], "errorunsupportedprotocol");
+}
+
+// Components are matched like Omaha app ids, a component known to /service/update2 is known here
+#[test]
+fn components_match_like_omaha_app_ids() {
+    let server = TestServer::start();
+    let mut request = client_request(Channel::Dev);
+    request.apps = vec![
+        AppRequest{ appid:format!("{{{}}}", COMPONENT.to_uppercase()), version:String::from("1.0.0.0") },
+        AppRequest{ appid:String::from("unknowncomponent"), version:String::from("1.0.0.0") },
+    ];
+    let latest = server.post("/latest", &serde_json::to_value(&request).unwrap());
+    assert_eq!(latest.status_code, 200);
+    let apps = &latest.json()["apps"];
+    assert_eq!(apps[0]["status"], "ok", "{apps}");
+    assert_eq!(apps[0]["version"], "4.10.0.0");
+    assert_eq!(apps[0]["appid"], "{WIDEVINE}");
+    assert_eq!(apps[1]["status"], "errorunknownapplication");
+}
+
+// 2.0 needs a newer kernel than the client runs, 1.5 is the last version it can update to
+const OLD_OS_CATALOG:&str = r#"{
+    "stable":[], "beta":[], "canary":[], "extended":[],
+    "dev":[
+        {"major":2,"minor":0,"build":0,"patch":0,"count":0,"urls":[],
+            "builds":[{"platform":"Linux","arch":"x86","min_os_version":"6.0","server":"https://example.com/2.0.0.tar.gz"}]},
+        {"major":1,"minor":5,"build":0,"patch":0,"count":0,"urls":[],
+            "builds":[{"platform":"Linux","arch":"x86","min_os_version":"4.0","server":"https://example.com/1.5.0.tar.gz"}]}
+    ]
+}"#;
+
+#[test]
+fn download_the_last_version_supporting_the_os() {
+    let server = TestServer::with_catalog(OLD_OS_CATALOG);
+    let mut request = client_request(Channel::Dev);
+    request.os.version = String::from("5.10");
+
+    let latest = server.post("/latest", &serde_json::to_value(&request).unwrap());
+    assert_eq!(latest.status_code, 406);
+    let json = latest.json();
+    assert_eq!(json["status"], "errorosnotsupported");
+    assert_eq!(json["info"], "2.0.0.0 requires Linux 6.0, 1.5.0.0 is the last version supporting this OS");
+    assert_eq!(json["actions"], serde_json::json!(["download", "abandon"]));
+    latest.use_ids(&mut request);
+
+    let download = server.post("/download", &serde_json::to_value(&request).unwrap());
+    assert_eq!(download.status_code, 406);
+    let json = download.json();
+    assert_eq!(json["status"], "errorosnotsupported");
+    assert_eq!(json["downloadlink"], "https://example.com/1.5.0.tar.gz");
+    assert_eq!(json["actions"], serde_json::json!(["abandon", "retry"]));
+}